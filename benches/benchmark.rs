@@ -1,20 +1,16 @@
 use criterion::{Criterion, criterion_group, criterion_main};
-use func_sharp::{interpreter::Invocation, parser::{FuncParser, self}};
-use pest::Parser;
+use func_sharp::parser;
 
 static PRIME_SRC: &str = include_str!("prime.funcs");
 
 fn prime_benchmark(c: &mut Criterion) {
-    c.bench_function("prime 10000", |b| b.iter(|| func_sharp::interpreter::execute(parse(PRIME_SRC))));
+    c.bench_function("prime 10000", |b| b.iter(|| func_sharp::interpreter::execute(parser::parse_program(PRIME_SRC).unwrap()).unwrap()));
 }
 
-fn parse(src: &str) -> Vec<Invocation> {
-    FuncParser::parse(parser::Rule::program, src).unwrap().next().unwrap().into_inner().filter_map(|pair| match pair.as_rule() {
-        parser::Rule::invocation => Some(Invocation::from(pair)),
-        parser::Rule::EOI => None,
-        _ => unreachable!()
-    }).collect::<Vec<_>>()
+fn prime_bytecode_benchmark(c: &mut Criterion) {
+    let program = parser::parse_program(PRIME_SRC).unwrap();
+    c.bench_function("prime 10000 (bytecode)", |b| b.iter(|| func_sharp::interpreter::compile(&program).unwrap().run().unwrap()));
 }
 
-criterion_group!(benches, prime_benchmark);
-criterion_main!(benches);
\ No newline at end of file
+criterion_group!(benches, prime_benchmark, prime_bytecode_benchmark);
+criterion_main!(benches);
@@ -1,20 +1,44 @@
-mod consts;
+mod bytecode;
+mod check;
+pub mod consts;
 mod context;
 mod defined;
+mod error;
+mod function;
+mod instance;
+mod module;
+mod native;
+mod policy;
+mod runtime;
 mod scope;
 mod system;
+mod vm;
 
-use std::{cell::RefCell, mem::Discriminant, rc::Rc};
+use std::{cell::RefCell, collections::BTreeMap, fmt, rc::Rc};
 
 use pest::iterators::Pair;
 use std::mem;
 
-use crate::{parser, util::OptionalStatic};
+use crate::parser::{self, Span};
+
+pub use self::bytecode::{compile, Instruction, Operator, Program};
+pub use self::check::check;
+pub use self::error::{CompileError, EvalError, RuntimeError, TypeError};
+pub use self::function::FunctionValue;
+pub use self::instance::Interpreter;
+pub use self::module::ModuleSource;
+pub use self::native::NativeFunction;
+pub use self::policy::{Capture, IoPolicy};
+pub use self::runtime::{Limits, Runtime, DEFAULT_MAX_DEPTH, DEFAULT_MAX_STACK};
+pub use self::scope::{
+    default_variable_scope, FunctionScope, FunctionSignature, ReturnType, SignatureArgument,
+    VariableScope,
+};
 
 use self::{
+    scope::OverloadCache,
     context::ContextFunction,
     defined::DefinedFunction,
-    scope::{FunctionScope, FunctionSignature, VariableScope, ReturnType},
     system::SystemFunction,
 };
 
@@ -24,6 +48,7 @@ pub enum FunctionSource {
     System(SystemFunction),
     Context(ContextFunction),
     Defined(DefinedFunction),
+    Native(NativeFunction),
 }
 
 /// The invocation of a function (contains the name and raw [Argument]s)
@@ -31,16 +56,20 @@ pub enum FunctionSource {
 pub struct Invocation {
     name: String,
     args: Vec<Argument>,
+    span: Span,
+    overload: RefCell<Option<OverloadCache>>,
 }
 
 /// Any data that can be stored
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone)]
 pub enum Data {
     String(String),
     Number(f64),
     Boolean(bool),
     ControlFlow(ControlFlow),
     List(Vec<Rc<RefCell<Data>>>),
+    Map(BTreeMap<String, Rc<RefCell<Data>>>),
+    Function(FunctionValue),
     Unit,
 }
 
@@ -49,29 +78,26 @@ pub enum ControlFlow {
     Break,
     Continue,
     Return(Rc<RefCell<Data>>),
+    /// `return(f(...))`, which the function returning makes once it has finished so the call takes no stack
+    TailCall(FunctionValue, Vec<Rc<RefCell<Data>>>),
 }
 
 /// The raw argument
 #[derive(Debug, Clone)]
 pub enum Argument {
     Function(Invocation),
-    Data(Data),
-    Ident(String),
+    Data(Data, Span),
+    Ident(String, Span),
 }
 
-pub fn execute(program: Vec<Invocation>) {
-    let mut function_scope = FunctionScope::default();
-    let variable_scope = Rc::new(RefCell::new(scope::default_variable_scope()));
-
-    for invocation in program.iter() {
-        invocation.evaluate(&mut function_scope, variable_scope.clone(), variable_scope.clone());
-    }
+pub fn execute(program: Vec<Invocation>) -> Result<(), RuntimeError> {
+    Interpreter::new().evaluate(&program).map(|_| ())
 }
 
 #[macro_export]
 macro_rules! signature {
     ($name:expr, $return_type:expr, $repeating:expr, $($arg:expr),+) => {
-        FunctionSignature {
+        $crate::interpreter::FunctionSignature {
             name: $name,
             return_type: $return_type,
             repeating: $repeating,
@@ -79,7 +105,7 @@ macro_rules! signature {
         }
     };
     ($name:expr, $return_type:expr, $repeating:expr) => {
-        FunctionSignature {
+        $crate::interpreter::FunctionSignature {
             name: $name,
             return_type: $return_type,
             repeating: $repeating,
@@ -91,60 +117,138 @@ macro_rules! signature {
 impl Invocation {
     pub fn evaluate(
         &self,
-        function_scope: &mut FunctionScope,
+        runtime: &mut Runtime,
         variable_scope: Rc<RefCell<VariableScope>>,
         global_scope: Rc<RefCell<VariableScope>>,
-    ) -> Rc<RefCell<Data>> {
-        let got = function_scope
-            .get(
-                &self.name,
-                &self.args,
-                function_scope,
-                variable_scope.clone(),
-            )
-            .cloned();
+    ) -> Result<Rc<RefCell<Data>>, RuntimeError> {
+        runtime.step().map_err(|e| e.located(self.span))?;
+
+        let got = runtime
+            .functions
+            .get(self, variable_scope.clone())
+            .map_err(|e| e.located(self.span))?;
 
         if let Some(function) = got {
-            function.execute(&self.args, function_scope, variable_scope, global_scope)
-        } else {
-            panic!(
-                "Function not found: {} with signature: {:?}",
-                self.name, self.args
-            );
+            if let Some(tail_call) = self.tail_call(&function, runtime, variable_scope.clone(), global_scope.clone())? {
+                return Ok(tail_call);
+            }
+
+            return function
+                .execute(&self.args, runtime, variable_scope, global_scope)
+                .map_err(|e| e.located(self.span));
+        }
+
+        // A variable holding a function can be called like one
+        let variable = variable_scope.borrow().get(&self.name);
+        if let Some(variable) = variable {
+            if let Data::Function(function) = &*variable.borrow() {
+                let args = self
+                    .args
+                    .iter()
+                    .map(|arg| arg.eval(runtime, variable_scope.clone(), global_scope.clone()))
+                    .collect::<Result<Vec<_>, _>>()?;
+                return function.call(&args, runtime).map_err(|e| e.located(self.span));
+            }
+        }
+
+        Err(self.not_found(&runtime.functions, variable_scope).located(self.span))
+    }
+
+    /// If this is `return(f(...))` inside a user defined function and `f` is one too,
+    /// evaluate the arguments of `f` and hand the call back to the function returning
+    fn tail_call(
+        &self,
+        function: &FunctionSource,
+        runtime: &mut Runtime,
+        variable_scope: Rc<RefCell<VariableScope>>,
+        global_scope: Rc<RefCell<VariableScope>>,
+    ) -> Result<Option<Rc<RefCell<Data>>>, RuntimeError> {
+        let (FunctionSource::System(SystemFunction::Return), [Argument::Function(call)]) = (function, self.args.as_slice()) else {
+            return Ok(None);
+        };
+        if !runtime.in_function() {
+            return Ok(None);
+        }
+
+        let callee = match runtime.functions.get(call, variable_scope.clone()).map_err(|e| e.located(call.span))? {
+            Some(callee) if matches!(*callee, FunctionSource::Defined(_)) => callee,
+            _ => return Ok(None),
+        };
+
+        let args = call
+            .args
+            .iter()
+            .map(|arg| arg.eval(runtime, variable_scope.clone(), global_scope.clone()))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Some(Rc::new(RefCell::new(Data::ControlFlow(ControlFlow::TailCall(
+            FunctionValue::new(call.name.clone(), vec![callee]),
+            args,
+        ))))))
+    }
+
+    /// Where this invocation appears in its source
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    /// The error for when no function matches this invocation
+    pub(crate) fn not_found(
+        &self,
+        function_scope: &FunctionScope,
+        variable_scope: Rc<RefCell<VariableScope>>,
+    ) -> RuntimeError {
+        RuntimeError::FunctionNotFound {
+            name: self.name.clone(),
+            args: self
+                .args
+                .iter()
+                .map(|arg| {
+                    arg.return_type(function_scope, variable_scope.clone())
+                        .unwrap_or(ReturnType::Any)
+                })
+                .collect(),
         }
     }
 }
 
 impl From<Pair<'_, parser::Rule>> for Invocation {
     fn from(value: Pair<'_, parser::Rule>) -> Self {
+        let span = value.as_span().into();
         let mut inner = value.into_inner();
 
         let name = inner.next().unwrap().as_str().to_string();
         let args = inner.map(Argument::from).collect();
 
-        Self { name, args }
+        Self {
+            name,
+            args,
+            span,
+            overload: RefCell::default(),
+        }
     }
 }
 
 impl Argument {
     pub fn eval(
         &self,
-        function_scope: &mut FunctionScope,
+        runtime: &mut Runtime,
         variable_scope: Rc<RefCell<VariableScope>>,
         global_scope: Rc<RefCell<VariableScope>>,
-    ) -> Rc<RefCell<Data>> {
+    ) -> Result<Rc<RefCell<Data>>, RuntimeError> {
         match self {
-            Argument::Data(data) => Rc::new(RefCell::new(data.clone())),
+            Argument::Data(data, _) => Ok(Rc::new(RefCell::new(data.clone()))),
             Argument::Function(invocation) => invocation.evaluate(
-                function_scope,
+                runtime,
                 variable_scope,
                 global_scope,
             ),
-            Argument::Ident(ident) => variable_scope
-                .borrow()
-                .get(ident)
-                .expect("Variable not found")
-                .clone(),
+            Argument::Ident(ident, span) => match variable_scope.borrow().get(ident) {
+                Some(data) => Ok(data),
+                // Naming a function that is not shadowed by a variable makes a value of it
+                None => FunctionValue::named(ident, runtime.functions.candidates(ident))
+                    .map(|function| Rc::new(RefCell::new(Data::Function(function))))
+                    .ok_or_else(|| RuntimeError::VariableNotFound(ident.clone()).located(*span)),
+            },
         }
     }
 
@@ -152,39 +256,39 @@ impl Argument {
         &self,
         function_scope: &FunctionScope,
         variable_scope: Rc<RefCell<VariableScope>>,
-    ) -> ReturnType {
+    ) -> Result<ReturnType, RuntimeError> {
         match self {
-            Argument::Function(func) => {
-                function_scope
-                    .get(&func.name, &func.args, function_scope, variable_scope)
-                    .unwrap_or_else(|| panic!("Function not found: {}", func.name))
-                    .signature()
-                    .get_ref()
-                    .return_type
-                    .clone()
-            }
-            Argument::Data(data) => ReturnType::Data(mem::discriminant(data)),
-            Argument::Ident(ident) => ReturnType::Data(mem::discriminant(
-                &*variable_scope
-                    .borrow()
-                    .get(ident)
-                    .expect("Variable not found")
-                    .borrow(),
-            )),
+            Argument::Function(func) => function_scope.return_type(func, variable_scope),
+            Argument::Data(data, _) => Ok(ReturnType::Data(mem::discriminant(data))),
+            Argument::Ident(ident, span) => match variable_scope.borrow().get(ident) {
+                Some(data) => Ok(ReturnType::Data(mem::discriminant(&*data.borrow()))),
+                None if FunctionValue::named(ident, function_scope.candidates(ident)).is_some() => {
+                    Ok(consts::return_function())
+                }
+                None => Err(RuntimeError::VariableNotFound(ident.clone()).located(*span)),
+            },
+        }
+    }
+
+    pub fn ident(&self) -> Result<String, RuntimeError> {
+        match self {
+            Argument::Ident(i, _) => Ok(i.clone()),
+            _ => Err(RuntimeError::InvalidArgument("expected an identifier".into()).located(self.span())),
         }
     }
 
-    pub fn ident(&self) -> String {
+    pub fn invocation(&self) -> Result<Invocation, RuntimeError> {
         match self {
-            Argument::Ident(i) => i.clone(),
-            _ => panic!("Argument is not an ident"),
+            Argument::Function(f) => Ok(f.clone()),
+            _ => Err(RuntimeError::InvalidArgument("expected a function invocation".into()).located(self.span())),
         }
     }
 
-    pub fn invocation(&self) -> Invocation {
+    /// Where this argument appears in its source
+    pub fn span(&self) -> Span {
         match self {
-            Argument::Function(f) => f.clone(),
-            _ => panic!("Argument is not a function"),
+            Argument::Function(f) => f.span,
+            Argument::Data(_, span) | Argument::Ident(_, span) => *span,
         }
     }
 }
@@ -192,120 +296,307 @@ impl Argument {
 impl From<Pair<'_, parser::Rule>> for Argument {
     fn from(value: Pair<'_, parser::Rule>) -> Self {
         let value = value.into_inner().next().unwrap();
+        let span = value.as_span().into();
         match value.as_rule() {
-            parser::Rule::string => Argument::Data(Data::String(
-                value.into_inner().next().unwrap().as_str().to_string(),
-            )),
-            parser::Rule::number => Argument::Data(Data::Number(value.as_str().parse().unwrap())),
+            parser::Rule::string => {
+                let text = value.into_inner().next().unwrap();
+                Argument::Data(Data::String(match text.as_rule() {
+                    parser::Rule::raw_string => text.into_inner().next().unwrap().as_str().to_string(),
+                    _ => parser::unescape(text.as_str()),
+                }), span)
+            }
+            parser::Rule::number => Argument::Data(Data::Number(value.as_str().parse().unwrap()), span),
             parser::Rule::invocation => Argument::Function(value.into()),
-            parser::Rule::ident => Argument::Ident(value.as_str().to_string()),
+            parser::Rule::boolean => Argument::Data(Data::Boolean(value.as_str() == "true"), span),
+            parser::Rule::control => Argument::Data(Data::ControlFlow(match value.as_str() {
+                "break" => ControlFlow::Break,
+                _ => ControlFlow::Continue,
+            }), span),
+            parser::Rule::ident | parser::Rule::operator => Argument::Ident(value.as_str().to_string(), span),
             _ => unreachable!(),
         }
     }
 }
 
 impl Data {
-    fn number(&self) -> f64 {
+    /// The name of this data's type as it is written in function definitions
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Data::String(_) => "string",
+            Data::Number(_) => "number",
+            Data::Boolean(_) => "boolean",
+            Data::ControlFlow(_) => "control flow",
+            Data::List(_) => "list",
+            Data::Map(_) => "map",
+            Data::Function(_) => "function",
+            Data::Unit => "unit",
+        }
+    }
+
+    fn mismatch(&self, expected: &'static str) -> RuntimeError {
+        RuntimeError::TypeMismatch {
+            expected,
+            found: self.type_name(),
+        }
+    }
+
+    /// The string this data holds, or a type mismatch error if it is not a string
+    pub fn string(&self) -> Result<&str, RuntimeError> {
+        match self {
+            Data::String(s) => Ok(s),
+            _ => Err(self.mismatch("string")),
+        }
+    }
+
+    fn map(&self) -> Result<&BTreeMap<String, Rc<RefCell<Data>>>, RuntimeError> {
+        match self {
+            Data::Map(m) => Ok(m),
+            _ => Err(self.mismatch("map")),
+        }
+    }
+
+    fn map_mut(&mut self) -> Result<&mut BTreeMap<String, Rc<RefCell<Data>>>, RuntimeError> {
+        match self {
+            Data::Map(m) => Ok(m),
+            _ => Err(self.mismatch("map")),
+        }
+    }
+
+    fn function(&self) -> Result<FunctionValue, RuntimeError> {
         match self {
-            Data::Number(n) => *n,
-            _ => panic!("Data is not a number"),
+            Data::Function(function) => Ok(function.clone()),
+            _ => Err(self.mismatch("function")),
         }
     }
 
-    fn boolean(&self) -> bool {
+    /// The number this data holds, or a type mismatch error if it is not a number
+    pub fn number(&self) -> Result<f64, RuntimeError> {
         match self {
-            Data::Boolean(b) => *b,
-            _ => panic!("Data is not a boolean"),
+            Data::Number(n) => Ok(*n),
+            _ => Err(self.mismatch("number")),
         }
     }
 
-    fn list(&self) -> Vec<Rc<RefCell<Data>>> {
+    /// The boolean this data holds, or a type mismatch error if it is not a boolean
+    pub fn boolean(&self) -> Result<bool, RuntimeError> {
+        match self {
+            Data::Boolean(b) => Ok(*b),
+            _ => Err(self.mismatch("boolean")),
+        }
+    }
+
+    fn list(&self) -> Result<Vec<Rc<RefCell<Data>>>, RuntimeError> {
         match self{
-            Data::List(l) => l.clone(),
-            _ => panic!("Data is not a list"),
+            Data::List(l) => Ok(l.clone()),
+            _ => Err(self.mismatch("list")),
         }
     }
 
-    fn list_mut(&mut self) -> &mut Vec<Rc<RefCell<Data>>> {
+    /// A copy of the element at `index` of a list, if it is that long
+    fn element(&self, index: usize) -> Result<Option<Data>, RuntimeError> {
+        match self {
+            Data::List(l) => Ok(l.get(index).map(|element| element.borrow().clone())),
+            _ => Err(self.mismatch("list")),
+        }
+    }
+
+    /// The number of elements of a list or map, or bytes of a string, which count towards [`Limits::max_size`]
+    fn size(&self) -> usize {
+        match self {
+            Data::String(s) => s.len(),
+            Data::List(l) => l.len(),
+            Data::Map(m) => m.len(),
+            _ => 0,
+        }
+    }
+
+    fn list_mut(&mut self) -> Result<&mut Vec<Rc<RefCell<Data>>>, RuntimeError> {
         match self{
-            Data::List(l) => l,
-            _ => panic!("Data is not a list"),
+            Data::List(l) => Ok(l),
+            _ => Err(self.mismatch("list")),
         }
     }
 }
 
-impl ToString for Data {
-    fn to_string(&self) -> String {
+impl Data {
+    /// Display `self` inside the lists and maps in `displaying`, which shows a list or map
+    /// that contains itself as `[...]` or `{...}` instead of displaying it forever
+    fn fmt_nested(&self, f: &mut fmt::Formatter<'_>, displaying: &mut Vec<*const Data>) -> fmt::Result {
+        let id = self as *const Data;
         match self {
-            Data::String(s) => s.clone(),
-            Data::Number(n) => n.to_string(),
-            Data::Boolean(b) => b.to_string(),
-            Data::ControlFlow(c) => c.to_string(),
-            Data::List(l) => format!("[{}]", l.iter().map(|d| d.borrow().to_string()).collect::<Vec<_>>().join(", ")),
-            Data::Unit => "()".to_string(),
+            Data::List(_) if displaying.contains(&id) => write!(f, "[...]"),
+            Data::Map(_) if displaying.contains(&id) => write!(f, "{{...}}"),
+            Data::List(l) => {
+                displaying.push(id);
+                write!(f, "[")?;
+                for (i, element) in l.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    element.borrow().fmt_nested(f, displaying)?;
+                }
+                displaying.pop();
+                write!(f, "]")
+            }
+            Data::Map(m) => {
+                displaying.push(id);
+                write!(f, "{{")?;
+                for (i, (key, value)) in m.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{key}: ")?;
+                    value.borrow().fmt_nested(f, displaying)?;
+                }
+                displaying.pop();
+                write!(f, "}}")
+            }
+            Data::String(s) => write!(f, "{s}"),
+            Data::Number(n) => write!(f, "{n}"),
+            Data::Boolean(b) => write!(f, "{b}"),
+            Data::ControlFlow(c) => write!(f, "{c}"),
+            Data::Function(function) => write!(f, "{function}"),
+            Data::Unit => write!(f, "()"),
+        }
+    }
+
+    /// Compare `self` to `other` inside the pairs of lists and maps in `comparing`,
+    /// taking a pair that is met again to be equal so lists and maps that contain themselves still compare
+    fn eq_nested(&self, other: &Data, comparing: &mut Vec<(*const Data, *const Data)>) -> bool {
+        let pair = (self as *const Data, other as *const Data);
+        match (self, other) {
+            (Data::List(_), Data::List(_)) | (Data::Map(_), Data::Map(_)) if comparing.contains(&pair) => true,
+            (Data::List(a), Data::List(b)) => {
+                comparing.push(pair);
+                let equal = a.len() == b.len()
+                    && a.iter().zip(b).all(|(a, b)| a.borrow().eq_nested(&b.borrow(), comparing));
+                comparing.pop();
+                equal
+            }
+            (Data::Map(a), Data::Map(b)) => {
+                comparing.push(pair);
+                let equal = a.len() == b.len()
+                    && a.iter().zip(b).all(|((key_a, a), (key_b, b))| {
+                        key_a == key_b && a.borrow().eq_nested(&b.borrow(), comparing)
+                    });
+                comparing.pop();
+                equal
+            }
+            (Data::String(a), Data::String(b)) => a == b,
+            (Data::Number(a), Data::Number(b)) => a == b,
+            (Data::Boolean(a), Data::Boolean(b)) => a == b,
+            (Data::ControlFlow(a), Data::ControlFlow(b)) => a == b,
+            (Data::Function(a), Data::Function(b)) => a == b,
+            (Data::Unit, Data::Unit) => true,
+            _ => false,
         }
     }
 }
 
-impl ToString for ControlFlow {
-    fn to_string(&self) -> String {
+impl fmt::Display for Data {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.fmt_nested(f, &mut Vec::new())
+    }
+}
+
+impl PartialEq for Data {
+    fn eq(&self, other: &Self) -> bool {
+        self.eq_nested(other, &mut Vec::new())
+    }
+}
+
+impl fmt::Display for ControlFlow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            ControlFlow::Break => "break".into(),
-            ControlFlow::Continue => "continue".into(),
-            ControlFlow::Return(_) => "return".into(),
+            ControlFlow::Break => write!(f, "break"),
+            ControlFlow::Continue => write!(f, "continue"),
+            ControlFlow::Return(_) | ControlFlow::TailCall(..) => write!(f, "return"),
         }
     }
 }
 
 impl FunctionSource {
-    fn signature(&self) -> OptionalStatic<FunctionSignature> {
+    fn signature(&self) -> &FunctionSignature {
         match self {
             FunctionSource::System(func) => func.signature(),
             FunctionSource::Context(func) => func.signature(),
-            FunctionSource::Defined(func) => OptionalStatic::Owned(func.signature()),
+            FunctionSource::Defined(func) => func.signature(),
+            FunctionSource::Native(func) => func.signature(),
+        }
+    }
+
+    /// Call the function with already evaluated arguments, as when it is called through a [`FunctionValue`]
+    pub fn call(
+        &self,
+        args: &[Rc<RefCell<Data>>],
+        runtime: &mut Runtime,
+    ) -> Result<Rc<RefCell<Data>>, RuntimeError> {
+        match self {
+            FunctionSource::System(func) => func.execute(args, runtime, Rc::default()),
+            FunctionSource::Context(func) => Err(RuntimeError::InvalidArgument(format!(
+                "{} can not be called as a value",
+                func.signature().name
+            ))),
+            FunctionSource::Defined(func) => func.call(args, runtime),
+            FunctionSource::Native(func) => func.execute(args),
         }
     }
 
     pub fn execute(
         &self,
         args: &[Argument],
-        function_scope: &mut FunctionScope,
+        runtime: &mut Runtime,
         variable_scope: Rc<RefCell<VariableScope>>,
         global_scope: Rc<RefCell<VariableScope>>,
-    ) -> Rc<RefCell<Data>> {
-        match self {
-            FunctionSource::System(func) => func.execute(
-                &args
-                    .iter()
-                    .map(|arg| {
-                        arg.eval(function_scope, variable_scope.clone(), global_scope.clone())
-                    })
-                    .collect::<Vec<_>>(),
-                function_scope,
-                variable_scope,
-            ),
-            FunctionSource::Context(func) => func.execute(
+    ) -> Result<Rc<RefCell<Data>>, RuntimeError> {
+        if let FunctionSource::Context(func) = self {
+            return func.execute(
                 &context::to_context_args(
                     args,
-                    func.signature().get_ref(),
-                    function_scope,
+                    func.signature(),
+                    runtime,
                     variable_scope.clone(),
                     global_scope.clone(),
-                ),
-                function_scope,
+                )?,
+                runtime,
                 variable_scope,
                 global_scope,
-            ),
-            FunctionSource::Defined(func) => func.execute(
-                &args
+            );
+        }
+
+        let args = args
+            .iter()
+            .map(|arg| arg.eval(runtime, variable_scope.clone(), global_scope.clone()))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // An argument whose type was only known to be `any` may have picked an overload its value does not fit,
+        // so pick again from the values, as compiled programs do
+        let fitting = match self.signature().accepts_data(&args) {
+            true => None,
+            false => Some(
+                runtime
+                    .functions
+                    .candidates(&self.signature().name)
                     .iter()
-                    .map(|arg| {
-                        arg.eval(function_scope, variable_scope.clone(), global_scope.clone())
+                    .find(|function| {
+                        !matches!(***function, FunctionSource::Context(_)) && function.signature().accepts_data(&args)
                     })
-                    .collect::<Vec<_>>(),
-                function_scope,
-                global_scope,
+                    .cloned()
+                    .ok_or_else(|| RuntimeError::FunctionNotFound {
+                        name: self.signature().name.clone(),
+                        args: args
+                            .iter()
+                            .map(|arg| ReturnType::Data(mem::discriminant(&*arg.borrow())))
+                            .collect(),
+                    })?,
             ),
+        };
+
+        match fitting.as_deref().unwrap_or(self) {
+            FunctionSource::System(func) => func.execute(&args, runtime, variable_scope),
+            FunctionSource::Context(_) => unreachable!("Context functions are executed with their raw arguments"),
+            FunctionSource::Defined(func) => func.execute(&args, runtime, global_scope),
+            FunctionSource::Native(func) => func.execute(&args),
         }
     }
 }
@@ -0,0 +1,789 @@
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
+
+use crate::parser::Span;
+
+use super::{
+    context::{Conditional, ContextFunction},
+    defined::DefinedFunction,
+    scope::{self, FunctionScope, FunctionSignature},
+    system::SystemFunction,
+    Argument, CompileError, ControlFlow, Data, FunctionSource, Invocation, RuntimeError,
+};
+
+/// A single instruction for the stack machine in [`vm`](super::vm)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Instruction {
+    /// Push a copy of a constant
+    Constant(usize),
+    /// Push `()`
+    Unit,
+    /// Push the value bound to a local slot
+    LoadLocal(usize),
+    /// Push the value bound to a global slot
+    LoadGlobal(usize),
+    /// Bind a local slot to the popped value, like `let`
+    BindLocal(usize),
+    /// Bind a global slot to the popped value, like `let`
+    BindGlobal(usize),
+    /// Overwrite the data bound to a local slot with the popped value, like `=`
+    AssignLocal(usize),
+    /// Overwrite the data bound to a global slot with the popped value, like `=`
+    AssignGlobal(usize),
+    /// Discard the top of the stack
+    Pop,
+    /// Continue at the given instruction
+    Jump(usize),
+    /// Pop a boolean and continue at the given instruction if it is false
+    JumpIfFalse(usize),
+    /// Push the next element of the list in a local slot, counting in the slot after it,
+    /// or continue at the given instruction once there are no more
+    Next(usize, usize),
+    /// Pop two values and push the result of the operator
+    Binary(Operator),
+    /// Call a system or native function with the given number of arguments
+    CallBuiltin(usize, usize),
+    /// Call a compiled function with the given number of arguments
+    Call(usize, usize),
+    /// Call the first overload in a set that accepts the runtime types of the arguments
+    CallDynamic(usize, usize),
+    /// Replace the current function with a call to a compiled function with the given number of arguments
+    TailCall(usize, usize),
+    /// Leave the current function with the popped value
+    Return,
+}
+
+/// The system functions with a dedicated two argument instruction
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operator {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Equal,
+    GreaterThan,
+    GreaterThanOrEqual,
+    LessThan,
+    LessThanOrEqual,
+}
+
+impl Operator {
+    fn from_system(function: SystemFunction) -> Option<Self> {
+        Some(match function {
+            SystemFunction::Add => Operator::Add,
+            SystemFunction::Sub => Operator::Sub,
+            SystemFunction::Mul => Operator::Mul,
+            SystemFunction::Div => Operator::Div,
+            SystemFunction::Mod => Operator::Mod,
+            SystemFunction::Cmp => Operator::Equal,
+            SystemFunction::GreaterThan => Operator::GreaterThan,
+            SystemFunction::GreaterThanOrEqual => Operator::GreaterThanOrEqual,
+            SystemFunction::LessThan => Operator::LessThan,
+            SystemFunction::LessThanOrEqual => Operator::LessThanOrEqual,
+            _ => return None,
+        })
+    }
+}
+
+/// A program lowered to bytecode.
+///
+/// Variables live in numbered slots and every call is bound to its function while compiling,
+/// so running it never looks anything up by name
+#[derive(Debug)]
+pub struct Program {
+    pub(super) main: Chunk,
+    pub(super) functions: Vec<CompiledFunction>,
+    pub(super) constants: Vec<Data>,
+    pub(super) builtins: Vec<Rc<FunctionSource>>,
+    pub(super) overloads: Vec<Vec<Callee>>,
+    pub(super) globals: Vec<Global>,
+}
+
+/// A sequence of instructions along with the source of each one
+#[derive(Debug, Default)]
+pub(super) struct Chunk {
+    pub(super) code: Vec<Instruction>,
+    pub(super) spans: Vec<Span>,
+    /// The name of each local slot
+    pub(super) locals: Vec<String>,
+}
+
+#[derive(Debug)]
+pub(super) struct CompiledFunction {
+    pub(super) signature: FunctionSignature,
+    pub(super) chunk: Chunk,
+}
+
+#[derive(Debug)]
+pub(super) struct Global {
+    pub(super) name: String,
+    pub(super) initial: Option<Data>,
+}
+
+/// One overload of a [`Instruction::CallDynamic`]
+#[derive(Debug, Clone, Copy)]
+pub(super) enum Callee {
+    Builtin(usize),
+    Defined(usize),
+}
+
+impl Program {
+    /// Print the instructions of every chunk, one per line
+    pub fn disassemble(&self) -> String {
+        let mut output = String::new();
+
+        let mut chunk = |name: &str, chunk: &Chunk| {
+            output.push_str(&format!("{name}:\n"));
+            for (i, instruction) in chunk.code.iter().enumerate() {
+                output.push_str(&format!("{i:>6} {instruction:?}\n"));
+            }
+        };
+
+        chunk("main", &self.main);
+        for function in self.functions.iter() {
+            chunk(&function.signature.name, &function.chunk);
+        }
+
+        output
+    }
+}
+
+/// Compile a program against the default functions
+pub fn compile(program: &[Invocation]) -> Result<Program, CompileError> {
+    compile_with(program, &FunctionScope::default())
+}
+
+/// Compile a program, binding calls to the functions in `functions` or defined by the program itself
+pub(super) fn compile_with(
+    program: &[Invocation],
+    functions: &FunctionScope,
+) -> Result<Program, CompileError> {
+    let mut compiler = Compiler {
+        functions,
+        program: Program {
+            main: Chunk::default(),
+            functions: Vec::new(),
+            constants: Vec::new(),
+            builtins: Vec::new(),
+            overloads: Vec::new(),
+            globals: Vec::new(),
+        },
+        globals: HashMap::new(),
+        builtins: HashMap::new(),
+        defined: HashMap::new(),
+        pending: Vec::new(),
+    };
+
+    let defaults = scope::default_variable_scope();
+    let mut defaults = defaults.iter().collect::<Vec<_>>();
+    defaults.sort_by(|a, b| a.0.cmp(b.0));
+    for (name, value) in defaults {
+        compiler.globals.insert(name.clone(), compiler.program.globals.len());
+        compiler.program.globals.push(Global {
+            name: name.clone(),
+            initial: Some(value.borrow().clone()),
+        });
+    }
+
+    let mut main = Builder::default();
+    if let Some((last, rest)) = program.split_last() {
+        for invocation in rest {
+            compiler.statement_invocation(&mut main, invocation)?;
+        }
+        compiler.invocation(&mut main, last, true)?;
+    } else {
+        main.emit(Instruction::Unit, Span::default());
+    }
+    main.emit(Instruction::Return, Span::default());
+    compiler.program.main = main.chunk;
+
+    // Bodies are compiled last so they can call functions and use globals defined after them
+    for (index, function) in std::mem::take(&mut compiler.pending) {
+        let mut builder = Builder {
+            scopes: vec![HashMap::new()],
+            function: Some(index),
+            ..Default::default()
+        };
+        for name in function.argument_names() {
+            builder.declare_local(name);
+        }
+
+        for arg in function.body() {
+            compiler.statement(&mut builder, arg)?;
+        }
+        let end = function.body().last().map(Argument::span).unwrap_or_default();
+        builder.emit(Instruction::Unit, end);
+        builder.emit(Instruction::Return, end);
+
+        compiler.program.functions[index].chunk = builder.chunk;
+    }
+
+    Ok(compiler.program)
+}
+
+struct Compiler<'a> {
+    functions: &'a FunctionScope,
+    program: Program,
+    globals: HashMap<String, usize>,
+    /// The index of each builtin in [`Program::builtins`], keyed by address
+    builtins: HashMap<*const FunctionSource, usize>,
+    /// The indices of compiled functions by name, in definition order
+    defined: HashMap<String, Vec<usize>>,
+    /// Functions whose bodies still need compiling
+    pending: Vec<(usize, DefinedFunction)>,
+}
+
+/// The chunk currently being compiled
+#[derive(Default)]
+struct Builder {
+    chunk: Chunk,
+    /// Lexical scopes of local slots, innermost last. Empty at the top level of the program
+    scopes: Vec<HashMap<String, usize>>,
+    loops: Vec<Loop>,
+    /// The index of the function being compiled, or `None` for the top level of the program
+    function: Option<usize>,
+}
+
+struct Loop {
+    start: usize,
+    breaks: Vec<usize>,
+}
+
+#[derive(Clone, Copy)]
+enum Slot {
+    Local(usize),
+    Global(usize),
+}
+
+/// A function a call could resolve to
+enum Resolved {
+    Builtin(Rc<FunctionSource>),
+    Defined(usize),
+}
+
+enum Control<'a> {
+    Break,
+    Continue,
+    /// With the value returned, or unit without one
+    Return(Option<&'a Argument>),
+}
+
+impl Builder {
+    fn emit(&mut self, instruction: Instruction, span: Span) -> usize {
+        self.chunk.code.push(instruction);
+        self.chunk.spans.push(span);
+        self.chunk.code.len() - 1
+    }
+
+    /// Point the jump at `index` to the next instruction
+    fn patch(&mut self, index: usize) {
+        let target = self.chunk.code.len();
+        match &mut self.chunk.code[index] {
+            Instruction::Jump(to) | Instruction::JumpIfFalse(to) | Instruction::Next(_, to) => *to = target,
+            _ => unreachable!(),
+        }
+    }
+
+    fn declare_local(&mut self, name: &str) -> usize {
+        let scope = self.scopes.last_mut().unwrap();
+        if let Some(&slot) = scope.get(name) {
+            return slot;
+        }
+
+        let slot = self.chunk.locals.len();
+        self.chunk.locals.push(name.to_string());
+        scope.insert(name.to_string(), slot);
+        slot
+    }
+}
+
+impl Compiler<'_> {
+    fn lookup(&self, builder: &Builder, name: &str) -> Option<Slot> {
+        builder
+            .scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(name))
+            .map(|&slot| Slot::Local(slot))
+            .or_else(|| self.globals.get(name).map(|&slot| Slot::Global(slot)))
+    }
+
+    fn declare(&mut self, builder: &mut Builder, name: &str) -> Slot {
+        if !builder.scopes.is_empty() {
+            return Slot::Local(builder.declare_local(name));
+        }
+
+        let globals = &mut self.program.globals;
+        Slot::Global(*self.globals.entry(name.to_string()).or_insert_with(|| {
+            globals.push(Global {
+                name: name.to_string(),
+                initial: None,
+            });
+            globals.len() - 1
+        }))
+    }
+
+    fn resolve(&self, name: &str, count: usize) -> Vec<Resolved> {
+        let builtins = self
+            .functions
+            .candidates(name)
+            .iter()
+            .filter(|function| function.signature().accepts_count(count))
+            .map(|function| Resolved::Builtin(function.clone()));
+
+        let defined = self
+            .defined
+            .get(name)
+            .into_iter()
+            .flatten()
+            .filter(|&&index| self.program.functions[index].signature.accepts_count(count))
+            .map(|&index| Resolved::Defined(index));
+
+        builtins.chain(defined).collect()
+    }
+
+    fn builtin(&mut self, function: &Rc<FunctionSource>) -> usize {
+        let builtins = &mut self.program.builtins;
+        *self.builtins.entry(Rc::as_ptr(function)).or_insert_with(|| {
+            builtins.push(function.clone());
+            builtins.len() - 1
+        })
+    }
+
+    /// Whether the argument is `break`, `continue` or `return(...)`
+    fn control<'a>(&self, arg: &'a Argument) -> Option<Control<'a>> {
+        match arg {
+            Argument::Data(Data::ControlFlow(ControlFlow::Break), _) => Some(Control::Break),
+            Argument::Data(Data::ControlFlow(ControlFlow::Continue), _) => Some(Control::Continue),
+            Argument::Function(invocation) => self.control_invocation(invocation),
+            Argument::Data(..) | Argument::Ident(..) => None,
+        }
+    }
+
+    fn control_invocation<'a>(&self, invocation: &'a Invocation) -> Option<Control<'a>> {
+        match self.resolve(&invocation.name, invocation.args.len()).as_slice() {
+            [Resolved::Builtin(function)] => match **function {
+                FunctionSource::System(SystemFunction::Break) => Some(Control::Break),
+                FunctionSource::System(SystemFunction::Continue) => Some(Control::Continue),
+                FunctionSource::System(SystemFunction::Return | SystemFunction::ReturnUnit) => {
+                    Some(Control::Return(invocation.args.first()))
+                }
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    fn control_flow(
+        &mut self,
+        builder: &mut Builder,
+        control: Control,
+        span: Span,
+    ) -> Result<(), CompileError> {
+        match control {
+            Control::Break => {
+                let jump = builder.emit(Instruction::Jump(0), span);
+                builder
+                    .loops
+                    .last_mut()
+                    .ok_or_else(|| unsupported("`break` outside of a loop", span))?
+                    .breaks
+                    .push(jump);
+            }
+            Control::Continue => {
+                let start = builder
+                    .loops
+                    .last()
+                    .ok_or_else(|| unsupported("`continue` outside of a loop", span))?
+                    .start;
+                builder.emit(Instruction::Jump(start), span);
+            }
+            Control::Return(value) => {
+                let Some(function) = builder.function else {
+                    return Err(unsupported("`return` outside of a function", span));
+                };
+
+                match value.and_then(|value| self.tail_call(function, value)) {
+                    Some((callee, call)) => {
+                        self.arguments(builder, &call.args)?;
+                        builder.emit(Instruction::TailCall(callee, call.args.len()), span);
+                    }
+                    None => {
+                        match value {
+                            Some(value) => self.expression(builder, value)?,
+                            None => {
+                                self.program.constants.push(Data::Unit);
+                                builder.emit(Instruction::Constant(self.program.constants.len() - 1), span);
+                            }
+                        }
+                        builder.emit(Instruction::Return, span);
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The function `value` calls if returning it from `function` can reuse the frame of `function`,
+    /// which is when the call can only resolve to a function declared to return the same type
+    fn tail_call<'a>(&self, function: usize, value: &'a Argument) -> Option<(usize, &'a Invocation)> {
+        let Argument::Function(call) = value else {
+            return None;
+        };
+
+        match self.resolve(&call.name, call.args.len()).as_slice() {
+            [Resolved::Defined(callee)]
+                if self.program.functions[*callee].signature.return_type
+                    == self.program.functions[function].signature.return_type =>
+            {
+                Some((*callee, call))
+            }
+            _ => None,
+        }
+    }
+
+    /// Compile an argument whose value is not used
+    fn statement(&mut self, builder: &mut Builder, arg: &Argument) -> Result<(), CompileError> {
+        match arg {
+            Argument::Function(invocation) => self.statement_invocation(builder, invocation),
+            _ => match self.control(arg) {
+                Some(control) => self.control_flow(builder, control, arg.span()),
+                None => {
+                    self.expression(builder, arg)?;
+                    builder.emit(Instruction::Pop, arg.span());
+                    Ok(())
+                }
+            },
+        }
+    }
+
+    fn statement_invocation(
+        &mut self,
+        builder: &mut Builder,
+        invocation: &Invocation,
+    ) -> Result<(), CompileError> {
+        match self.control_invocation(invocation) {
+            Some(control) => self.control_flow(builder, control, invocation.span),
+            None => self.invocation(builder, invocation, false),
+        }
+    }
+
+    /// Compile a body in its own scope, pushing the value of its last argument only if `value` is set
+    fn block(&mut self, builder: &mut Builder, body: &[&Argument], value: bool) -> Result<(), CompileError> {
+        builder.scopes.push(HashMap::new());
+        match body.split_last() {
+            Some((&last, rest)) if value => {
+                for &arg in rest {
+                    self.statement(builder, arg)?;
+                }
+                match self.control(last) {
+                    Some(control) => self.control_flow(builder, control, last.span())?,
+                    None => self.expression(builder, last)?,
+                }
+            }
+            _ => {
+                for &arg in body {
+                    self.statement(builder, arg)?;
+                }
+                if value {
+                    builder.emit(Instruction::Unit, Span::default());
+                }
+            }
+        }
+        builder.scopes.pop();
+
+        Ok(())
+    }
+
+    /// Compile an argument that pushes its value
+    fn expression(&mut self, builder: &mut Builder, arg: &Argument) -> Result<(), CompileError> {
+        match arg {
+            Argument::Data(data, span) => {
+                self.program.constants.push(data.clone());
+                builder.emit(Instruction::Constant(self.program.constants.len() - 1), *span);
+            }
+            Argument::Ident(name, span) => {
+                let instruction = match self.lookup(builder, name) {
+                    Some(Slot::Local(slot)) => Instruction::LoadLocal(slot),
+                    Some(Slot::Global(slot)) => Instruction::LoadGlobal(slot),
+                    None => {
+                        return Err(CompileError::VariableNotFound {
+                            name: name.clone(),
+                            span: *span,
+                        })
+                    }
+                };
+                builder.emit(instruction, *span);
+            }
+            Argument::Function(invocation) => self.invocation(builder, invocation, true)?,
+        }
+
+        Ok(())
+    }
+
+    fn arguments(&mut self, builder: &mut Builder, args: &[Argument]) -> Result<(), CompileError> {
+        args.iter().try_for_each(|arg| self.expression(builder, arg))
+    }
+
+    /// Compile a call, pushing its result only if `value` is set
+    fn invocation(
+        &mut self,
+        builder: &mut Builder,
+        invocation: &Invocation,
+        value: bool,
+    ) -> Result<(), CompileError> {
+        let span = invocation.span;
+        let count = invocation.args.len();
+
+        match self.resolve(&invocation.name, count).as_slice() {
+            [] => {
+                return Err(CompileError::FunctionNotFound {
+                    name: invocation.name.clone(),
+                    span,
+                })
+            }
+            [Resolved::Builtin(function)] => match &**function {
+                FunctionSource::Context(context) => {
+                    return self.context(builder, *context, invocation, value)
+                }
+                FunctionSource::System(
+                    SystemFunction::Break
+                    | SystemFunction::Continue
+                    | SystemFunction::Return
+                    | SystemFunction::ReturnUnit,
+                ) => {
+                    return Err(unsupported(
+                        &format!("`{}` used as a value", invocation.name),
+                        span,
+                    ))
+                }
+                FunctionSource::System(system) => {
+                    self.arguments(builder, &invocation.args)?;
+                    match Operator::from_system(*system) {
+                        Some(operator) if count == 2 => {
+                            builder.emit(Instruction::Binary(operator), span)
+                        }
+                        _ => {
+                            let index = self.builtin(function);
+                            builder.emit(Instruction::CallBuiltin(index, count), span)
+                        }
+                    };
+                }
+                FunctionSource::Native(_) => {
+                    self.arguments(builder, &invocation.args)?;
+                    let index = self.builtin(function);
+                    builder.emit(Instruction::CallBuiltin(index, count), span);
+                }
+                FunctionSource::Defined(_) => {
+                    return Err(unsupported("calling a function defined outside of the program", span))
+                }
+            },
+            &[Resolved::Defined(index)] => {
+                self.arguments(builder, &invocation.args)?;
+                builder.emit(Instruction::Call(index, count), span);
+            }
+            overloads => {
+                let callees = overloads
+                    .iter()
+                    .map(|overload| match overload {
+                        Resolved::Builtin(function) => match **function {
+                            FunctionSource::System(_) | FunctionSource::Native(_) => {
+                                Ok(Callee::Builtin(self.builtin(function)))
+                            }
+                            _ => Err(unsupported(
+                                &format!("overloading `{}`", invocation.name),
+                                span,
+                            )),
+                        },
+                        Resolved::Defined(index) => Ok(Callee::Defined(*index)),
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                self.arguments(builder, &invocation.args)?;
+                self.program.overloads.push(callees);
+                builder.emit(
+                    Instruction::CallDynamic(self.program.overloads.len() - 1, count),
+                    span,
+                );
+            }
+        }
+
+        if !value {
+            builder.emit(Instruction::Pop, span);
+        }
+
+        Ok(())
+    }
+
+    fn context(
+        &mut self,
+        builder: &mut Builder,
+        function: ContextFunction,
+        invocation: &Invocation,
+        value: bool,
+    ) -> Result<(), CompileError> {
+        let span = invocation.span;
+        let args = &invocation.args;
+
+        match function {
+            ContextFunction::Let => {
+                let name = args[0].ident().map_err(|error| invalid(error, span))?;
+                self.expression(builder, &args[1])?;
+                let instruction = match self.declare(builder, &name) {
+                    Slot::Local(slot) => Instruction::BindLocal(slot),
+                    Slot::Global(slot) => Instruction::BindGlobal(slot),
+                };
+                builder.emit(instruction, span);
+            }
+            ContextFunction::Assign => {
+                let name = args[0].ident().map_err(|error| invalid(error, span))?;
+                self.expression(builder, &args[1])?;
+                let instruction = match self.lookup(builder, &name) {
+                    Some(Slot::Local(slot)) => Instruction::AssignLocal(slot),
+                    Some(Slot::Global(slot)) => Instruction::AssignGlobal(slot),
+                    None => {
+                        return Err(CompileError::VariableNotFound {
+                            name,
+                            span: args[0].span(),
+                        })
+                    }
+                };
+                builder.emit(instruction, span);
+            }
+            ContextFunction::If => {
+                let Conditional { body, alternatives } =
+                    Conditional::new(&args[1..]).map_err(|error| invalid(error, span))?;
+
+                let mut exits = Vec::new();
+                let mut branches = std::iter::once((Some(&args[0]), body))
+                    .chain(alternatives)
+                    .peekable();
+                while let Some((condition, body)) = branches.next() {
+                    let skip = match condition {
+                        Some(condition) => {
+                            self.expression(builder, condition)?;
+                            Some(builder.emit(Instruction::JumpIfFalse(0), span))
+                        }
+                        None => None,
+                    };
+
+                    self.block(builder, &body, value)?;
+
+                    if branches.peek().is_some() || (skip.is_some() && value) {
+                        exits.push(builder.emit(Instruction::Jump(0), span));
+                    }
+                    if let Some(skip) = skip {
+                        builder.patch(skip);
+                    }
+
+                    // Without `else` the value when no branch runs is unit
+                    if branches.peek().is_none() && condition.is_some() && value {
+                        builder.emit(Instruction::Unit, span);
+                    }
+                }
+
+                for exit in exits {
+                    builder.patch(exit);
+                }
+                return Ok(());
+            }
+            ContextFunction::While => {
+                let start = builder.chunk.code.len();
+                self.expression(builder, &args[0])?;
+                let exit = builder.emit(Instruction::JumpIfFalse(0), span);
+
+                builder.loops.push(Loop {
+                    start,
+                    breaks: Vec::new(),
+                });
+                builder.scopes.push(HashMap::new());
+                for arg in args[1..].iter() {
+                    self.statement(builder, arg)?;
+                }
+                builder.scopes.pop();
+                builder.emit(Instruction::Jump(start), span);
+
+                let finished = builder.loops.pop().unwrap();
+                builder.patch(exit);
+                for jump in finished.breaks {
+                    builder.patch(jump);
+                }
+            }
+            ContextFunction::For => {
+                let name = args[0].ident().map_err(|error| invalid(error, span))?;
+                builder.scopes.push(HashMap::new());
+
+                // The list and the index of the next element go in slots no variable can be named
+                self.expression(builder, &args[1])?;
+                let list = builder.declare_local("for list");
+                builder.emit(Instruction::BindLocal(list), span);
+                self.program.constants.push(Data::Number(0.));
+                builder.emit(Instruction::Constant(self.program.constants.len() - 1), span);
+                let index = builder.declare_local("for index");
+                builder.emit(Instruction::BindLocal(index), span);
+
+                let start = builder.emit(Instruction::Next(list, 0), span);
+                let variable = builder.declare_local(&name);
+                builder.emit(Instruction::BindLocal(variable), span);
+
+                builder.loops.push(Loop {
+                    start,
+                    breaks: Vec::new(),
+                });
+                for arg in args[2..].iter() {
+                    self.statement(builder, arg)?;
+                }
+                builder.emit(Instruction::Jump(start), span);
+
+                let finished = builder.loops.pop().unwrap();
+                builder.patch(start);
+                for jump in finished.breaks {
+                    builder.patch(jump);
+                }
+                builder.scopes.pop();
+            }
+            ContextFunction::Fn => {
+                if builder.function.is_some() || !builder.scopes.is_empty() || !builder.loops.is_empty() {
+                    return Err(unsupported("defining a function inside a block", span));
+                }
+
+                let function = DefinedFunction::new(args, Rc::new(RefCell::default()))
+                    .map_err(|error| invalid(error, span))?;
+
+                let index = self.program.functions.len();
+                self.program.functions.push(CompiledFunction {
+                    signature: function.signature().clone(),
+                    chunk: Chunk::default(),
+                });
+                self.defined
+                    .entry(function.signature().name.clone())
+                    .or_default()
+                    .push(index);
+                self.pending.push((index, function));
+            }
+            ContextFunction::Import => return Err(unsupported("`import`", span)),
+            ContextFunction::Lambda => return Err(unsupported("`lambda`", span)),
+        }
+
+        if value {
+            builder.emit(Instruction::Unit, span);
+        }
+
+        Ok(())
+    }
+}
+
+fn unsupported(construct: &str, span: Span) -> CompileError {
+    CompileError::Unsupported {
+        construct: construct.to_string(),
+        span,
+    }
+}
+
+fn invalid(error: RuntimeError, span: Span) -> CompileError {
+    CompileError::Invalid {
+        span: error.span().unwrap_or(span),
+        error,
+    }
+}
@@ -0,0 +1,643 @@
+use std::{
+    collections::{HashMap, HashSet},
+    mem,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+use crate::parser::Span;
+
+use super::{
+    context::{Conditional, ContextFunction},
+    defined::DefinedFunction,
+    module::Module,
+    policy::IoPolicy,
+    scope::{self, FunctionScope, FunctionSignature, ReturnType, SignatureArgument, VariableScope},
+    system::SystemFunction,
+    Argument, Data, FunctionSource, Invocation, RuntimeError, TypeError,
+};
+
+/// Check every call in a program against the functions it could resolve to, without running it
+pub fn check(program: &[Invocation]) -> Result<(), Vec<TypeError>> {
+    check_with(
+        program,
+        &FunctionScope::default(),
+        &scope::default_variable_scope(),
+        None,
+        &IoPolicy::default(),
+    )
+}
+
+/// Check a program that will run with the given functions and global variables already defined.
+/// Imports are resolved relative to the file at `path`, or the working directory without one,
+/// and only followed where `io` allows the program to import
+pub(super) fn check_with(
+    program: &[Invocation],
+    functions: &FunctionScope,
+    globals: &VariableScope,
+    path: Option<&Path>,
+    io: &IoPolicy,
+) -> Result<(), Vec<TypeError>> {
+    let globals: HashMap<_, _> = globals
+        .iter()
+        .map(|(name, value)| (name.clone(), ReturnType::Data(mem::discriminant(&*value.borrow()))))
+        .collect();
+
+    let mut checker = Checker {
+        functions,
+        defined: HashMap::new(),
+        scopes: vec![globals.clone()],
+        globals,
+        assigned_in_functions: HashSet::new(),
+        pending: Vec::new(),
+        function: None,
+        lambdas: 0,
+        path: path.map(Path::to_path_buf),
+        io,
+        importing: Vec::new(),
+        imported: HashSet::new(),
+        errors: Vec::new(),
+        silent: 0,
+    };
+
+    for invocation in program {
+        checker.collect_assignments(invocation, false);
+    }
+
+    for invocation in program {
+        checker.invocation(invocation);
+    }
+
+    // Bodies run whenever the function is called, so they are checked against every type a global has had
+    let globals = checker.globals.clone();
+    checker.scopes = vec![globals];
+    for (function, span) in mem::take(&mut checker.pending) {
+        checker.function_body(&function, span);
+    }
+
+    if checker.errors.is_empty() {
+        Ok(())
+    } else {
+        checker.errors.sort_by_key(|error| error.span().start);
+        Err(checker.errors)
+    }
+}
+
+/// Infers the type of every expression, tracking variable types through their bindings.
+///
+/// The checker is conservative: whenever a variable could hold different types depending on
+/// the path taken at run time its type becomes [`ReturnType::Any`], which matches anything
+struct Checker<'a> {
+    functions: &'a FunctionScope,
+    /// Signatures of functions defined by the program, in definition order
+    defined: HashMap<String, Vec<FunctionSignature>>,
+    /// Variable types, innermost scope last
+    scopes: Vec<HashMap<String, ReturnType>>,
+    /// Every type each global has been bound to
+    globals: HashMap<String, ReturnType>,
+    /// Variables assigned with `=` inside a function or lambda body, which can change type whenever it is called
+    assigned_in_functions: HashSet<String>,
+    /// Functions whose bodies still need checking, with the span of their definition
+    pending: Vec<(DefinedFunction, Span)>,
+    /// The signature of the function whose body is being checked
+    function: Option<FunctionSignature>,
+    /// The number of lambda bodies being checked, which may use variables declared after they are created
+    lambdas: usize,
+    /// The file the program was read from
+    path: Option<PathBuf>,
+    io: &'a IoPolicy,
+    /// The canonical and imported paths of the modules being loaded, innermost last
+    importing: Vec<(PathBuf, String)>,
+    /// Modules that have been imported, with the namespace they were imported under
+    imported: HashSet<(PathBuf, String)>,
+    errors: Vec<TypeError>,
+    /// Errors are discarded while this is non-zero
+    silent: usize,
+}
+
+/// The most specific type covering both `a` and `b`
+fn join(a: &ReturnType, b: &ReturnType) -> ReturnType {
+    if a == b {
+        a.clone()
+    } else {
+        ReturnType::Any
+    }
+}
+
+impl Checker<'_> {
+    fn error(&mut self, error: TypeError) {
+        if self.silent == 0 {
+            self.errors.push(error);
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Option<ReturnType> {
+        self.scopes.iter().rev().find_map(|scope| scope.get(name)).cloned()
+    }
+
+    fn declare(&mut self, name: &str, mut data_type: ReturnType) {
+        if self.assigned_in_functions.contains(name) {
+            data_type = ReturnType::Any;
+        }
+
+        if self.scopes.len() == 1 {
+            let joined = match self.globals.get(name) {
+                Some(previous) => join(previous, &data_type),
+                None => data_type.clone(),
+            };
+            self.globals.insert(name.to_string(), joined);
+        }
+
+        self.scopes
+            .last_mut()
+            .unwrap()
+            .insert(name.to_string(), data_type);
+    }
+
+    fn assign(&mut self, name: &str, data_type: ReturnType, span: Span) {
+        let Some(depth) = self.scopes.iter().rposition(|scope| scope.contains_key(name)) else {
+            return self.error(TypeError::VariableNotFound {
+                name: name.to_string(),
+                span,
+            });
+        };
+
+        let joined = join(&self.scopes[depth][name], &data_type);
+        self.scopes[depth].insert(name.to_string(), joined.clone());
+        if depth == 0 {
+            let joined = join(self.globals.get(name).unwrap_or(&joined), &joined);
+            self.globals.insert(name.to_string(), joined);
+        }
+    }
+
+    /// Merge the variable types of another path through the program into the current ones
+    fn join_with(&mut self, other: &[HashMap<String, ReturnType>]) {
+        for (scope, other) in self.scopes.iter_mut().zip(other.iter()) {
+            for (name, other_type) in other {
+                let joined = match scope.get(name) {
+                    Some(current) => join(current, other_type),
+                    None => other_type.clone(),
+                };
+                scope.insert(name.clone(), joined);
+            }
+        }
+    }
+
+    /// Find every `=` inside a function or lambda body
+    fn collect_assignments(&mut self, invocation: &Invocation, in_function: bool) {
+        let in_function = in_function
+            || invocation.name == ContextFunction::Fn.signature().name
+            || invocation.name == ContextFunction::Lambda.signature().name;
+
+        if in_function && invocation.name == ContextFunction::Assign.signature().name {
+            if let Some(Argument::Ident(name, _)) = invocation.args.first() {
+                self.assigned_in_functions.insert(name.clone());
+            }
+        }
+
+        for arg in invocation.args.iter() {
+            if let Argument::Function(invocation) = arg {
+                self.collect_assignments(invocation, in_function);
+            }
+        }
+    }
+
+    fn candidates(&self, name: &str, count: usize) -> Vec<(&FunctionSignature, Option<&Rc<FunctionSource>>)> {
+        let builtins = self
+            .functions
+            .candidates(name)
+            .iter()
+            .map(|function| (function.signature(), Some(function)));
+
+        let defined = self
+            .defined
+            .get(name)
+            .into_iter()
+            .flatten()
+            .map(|signature| (signature, None));
+
+        builtins
+            .chain(defined)
+            .filter(|(signature, _)| signature.accepts_count(count))
+            .collect()
+    }
+
+    /// The type of an argument that is evaluated before it is passed on
+    fn argument(&mut self, arg: &Argument) -> ReturnType {
+        match arg {
+            Argument::Data(data, _) => ReturnType::Data(mem::discriminant(data)),
+            Argument::Ident(name, span) => match self.lookup(name) {
+                Some(data_type) => data_type,
+                None if self.names_function(name) => super::consts::return_function(),
+                // Including the variable the lambda is being bound to, so that it can call itself
+                None if self.lambdas > 0 => ReturnType::Any,
+                None => {
+                    self.error(TypeError::VariableNotFound {
+                        name: name.clone(),
+                        span: *span,
+                    });
+                    ReturnType::Any
+                }
+            },
+            Argument::Function(invocation) => self.invocation(invocation),
+        }
+    }
+
+    /// Check a body, returning the type of its last argument
+    fn statements<'b>(&mut self, body: impl IntoIterator<Item = &'b Argument>) -> ReturnType {
+        let mut last = super::consts::return_unit();
+        for arg in body {
+            last = if let Argument::Function(invocation) = arg {
+                self.invocation(invocation)
+            } else {
+                self.argument(arg)
+            };
+        }
+
+        last
+    }
+
+    /// Check a body that runs in its own scope at most once, returning the type of its last argument
+    fn branch<'b>(&mut self, body: impl IntoIterator<Item = &'b Argument>) -> ReturnType {
+        let before = self.scopes.clone();
+        self.scopes.push(HashMap::new());
+        let last = self.statements(body);
+        self.scopes.pop();
+        self.join_with(&before);
+        last
+    }
+
+    /// Check a body that runs any number of times, each time after `predicate` if there is one.
+    /// The first pass finds the types variables have when the loop repeats and its errors are discarded
+    fn repeat(&mut self, predicate: Option<&Argument>, body: &[Argument]) {
+        let before = self.scopes.clone();
+        self.silent += 1;
+        if let Some(predicate) = predicate {
+            self.condition(predicate);
+        }
+        self.block(body);
+        self.silent -= 1;
+        self.join_with(&before);
+
+        let head = self.scopes.clone();
+        if let Some(predicate) = predicate {
+            self.condition(predicate);
+        }
+        self.block(body);
+        self.join_with(&head);
+    }
+
+    /// Check the body of a loop, which declares its variables in a scope of its own
+    fn block(&mut self, body: &[Argument]) {
+        self.scopes.push(HashMap::new());
+        self.statements(body);
+        self.scopes.pop();
+    }
+
+    /// Check an argument that has to be a boolean
+    fn condition(&mut self, arg: &Argument) {
+        self.condition_type(arg, super::consts::return_boolean());
+    }
+
+    /// Check an argument that has to be of type `expected`
+    fn condition_type(&mut self, arg: &Argument, expected: ReturnType) {
+        let data_type = self.argument(arg);
+        if data_type != ReturnType::Any && data_type != expected {
+            self.error(TypeError::TypeMismatch {
+                expected,
+                found: data_type,
+                span: arg.span(),
+            });
+        }
+    }
+
+    fn function_body(&mut self, function: &DefinedFunction, span: Span) {
+        let signature = function.signature();
+        if signature.return_type != super::consts::return_unit()
+            && !function.body().iter().any(contains_return)
+        {
+            self.error(TypeError::MissingReturn {
+                name: signature.name.clone(),
+                expected: signature.return_type.clone(),
+                span,
+            });
+        }
+
+        self.function = Some(signature.clone());
+        self.scopes.push(parameters(function));
+        self.statements(function.body());
+
+        self.scopes.pop();
+        self.function = None;
+    }
+
+    /// Check the value given to `return`, which is unit without one, against the return type of the enclosing function
+    fn return_value(&mut self, invocation: &Invocation) -> ReturnType {
+        let (found, span) = match invocation.args.first() {
+            Some(arg) => (self.argument(arg), arg.span()),
+            None => (super::consts::return_unit(), invocation.span),
+        };
+
+        if let Some(signature) = &self.function {
+            if found != ReturnType::Any && signature.return_type != ReturnType::Any && found != signature.return_type {
+                self.error(TypeError::ReturnTypeMismatch {
+                    name: signature.name.clone(),
+                    expected: signature.return_type.clone(),
+                    found,
+                    span,
+                });
+            }
+        }
+
+        SystemFunction::Return.signature().return_type.clone()
+    }
+
+    /// Check an invocation and infer the type it returns
+    fn invocation(&mut self, invocation: &Invocation) -> ReturnType {
+        let candidates = self.candidates(&invocation.name, invocation.args.len());
+
+        // Calling a variable that may hold a function, whose arguments can only be checked at run time
+        if candidates.is_empty() {
+            if let Some(data_type) = self.lookup(&invocation.name).or((self.lambdas > 0).then_some(ReturnType::Any)) {
+                if data_type == ReturnType::Any || data_type == super::consts::return_function() {
+                    for arg in invocation.args.iter() {
+                        self.argument(arg);
+                    }
+                    return ReturnType::Any;
+                }
+            }
+        }
+
+        if let [(_, Some(function))] = candidates.as_slice() {
+            match &***function {
+                FunctionSource::Context(function) => return self.context(*function, invocation),
+                FunctionSource::System(SystemFunction::Return | SystemFunction::ReturnUnit) => {
+                    return self.return_value(invocation)
+                }
+                _ => {}
+            }
+        }
+
+        let signatures = candidates
+            .into_iter()
+            .map(|(signature, _)| signature.clone())
+            .collect::<Vec<_>>();
+
+        let types = invocation
+            .args
+            .iter()
+            .enumerate()
+            .map(|(i, arg)| {
+                let raw = signatures.iter().all(|signature| {
+                    matches!(signature.args[i.min(signature.args.len() - 1)], SignatureArgument::Raw)
+                });
+                if raw && !signatures.is_empty() {
+                    ReturnType::Any
+                } else {
+                    self.argument(arg)
+                }
+            })
+            .collect::<Vec<_>>();
+
+        // Overloads are tried in order, so only the first that definitely matches can be picked
+        let mut return_type = None;
+        for signature in signatures.iter() {
+            match accepts(signature, &types) {
+                Match::No => {}
+                Match::Maybe => {
+                    return_type = Some(match return_type {
+                        Some(previous) => join(&previous, &signature.return_type),
+                        None => signature.return_type.clone(),
+                    })
+                }
+                Match::Yes => {
+                    return_type = Some(match return_type {
+                        Some(previous) => join(&previous, &signature.return_type),
+                        None => signature.return_type.clone(),
+                    });
+                    break;
+                }
+            }
+        }
+
+        return_type.unwrap_or_else(|| {
+            self.error(TypeError::FunctionNotFound {
+                name: invocation.name.clone(),
+                args: types,
+                span: invocation.span,
+            });
+            ReturnType::Any
+        })
+    }
+
+    fn context(&mut self, function: ContextFunction, invocation: &Invocation) -> ReturnType {
+        let args = &invocation.args;
+        let span = invocation.span;
+
+        match function {
+            ContextFunction::Let => {
+                let data_type = self.argument(&args[1]);
+                match args[0].ident() {
+                    Ok(name) => self.declare(&name, data_type),
+                    Err(error) => self.error(TypeError::Invalid { error, span }),
+                }
+            }
+            ContextFunction::Assign => {
+                let data_type = self.argument(&args[1]);
+                match args[0].ident() {
+                    Ok(name) => self.assign(&name, data_type, args[0].span()),
+                    Err(error) => self.error(TypeError::Invalid { error, span }),
+                }
+            }
+            ContextFunction::If => {
+                self.condition(&args[0]);
+                let Conditional { body, alternatives } = match Conditional::new(&args[1..]) {
+                    Ok(conditional) => conditional,
+                    Err(error) => {
+                        self.error(TypeError::Invalid { error, span });
+                        return ReturnType::Any;
+                    }
+                };
+
+                // Without `else` no branch may run, which leaves the value unit
+                let mut value = self.branch(body);
+                let mut exhaustive = false;
+                for (condition, body) in alternatives {
+                    match condition {
+                        Some(condition) => self.condition(condition),
+                        None => exhaustive = true,
+                    }
+                    value = join(&value, &self.branch(body));
+                }
+
+                return if exhaustive {
+                    value
+                } else {
+                    join(&value, &super::consts::return_unit())
+                };
+            }
+            ContextFunction::While => self.repeat(Some(&args[0]), &args[1..]),
+            ContextFunction::For => {
+                self.condition_type(&args[1], super::consts::return_list());
+                match args[0].ident() {
+                    Ok(name) => {
+                        // Lists can hold anything, so nothing is known about the loop variable
+                        self.scopes.push(HashMap::from([(name, ReturnType::Any)]));
+                        self.repeat(None, &args[2..]);
+                        self.scopes.pop();
+                    }
+                    Err(error) => self.error(TypeError::Invalid { error, span }),
+                }
+            }
+            ContextFunction::Fn => match DefinedFunction::new(args, Rc::default()) {
+                Ok(function) => {
+                    self.define(function.signature());
+                    self.pending.push((function, span));
+                }
+                Err(error) => self.error(TypeError::Invalid { error, span }),
+            },
+            ContextFunction::Import => self.import(args, span),
+            ContextFunction::Lambda => self.lambda(args, span),
+        }
+
+        function.signature().return_type.clone()
+    }
+
+    /// Whether `name` refers to a function that can be made into a value
+    fn names_function(&self, name: &str) -> bool {
+        self.functions
+            .candidates(name)
+            .iter()
+            .any(|function| !matches!(**function, FunctionSource::Context(_)))
+            || self.defined.contains_key(name)
+    }
+
+    /// Check the body of a lambda where it is created, with the variables it captures.
+    /// It only runs once it is called, so variables that are not declared yet are taken to be declared by then
+    fn lambda(&mut self, args: &[Argument], span: Span) {
+        let lambda = match DefinedFunction::lambda(args, Rc::default()) {
+            Ok(lambda) => lambda,
+            Err(error) => return self.error(TypeError::Invalid { error, span }),
+        };
+
+        let function = self.function.replace(lambda.signature().clone());
+        self.scopes.push(parameters(&lambda));
+        self.lambdas += 1;
+        self.statements(lambda.body());
+        self.lambdas -= 1;
+        self.scopes.pop();
+        self.function = function;
+    }
+
+    fn define(&mut self, signature: &FunctionSignature) {
+        self.defined
+            .entry(signature.name.clone())
+            .or_default()
+            .push(signature.clone());
+    }
+
+    /// Define the functions of an imported module.
+    /// Their bodies are not checked, since they belong to another file
+    fn import(&mut self, args: &[Argument], span: Span) {
+        self.condition_type(&args[0], super::consts::return_string());
+        if args.len() > 2 {
+            let error = RuntimeError::InvalidArgument("import takes a path and an optional namespace".into());
+            return self.error(TypeError::Invalid { error, span });
+        }
+
+        // Paths computed at run time can only be followed at run time
+        let Argument::Data(Data::String(path), _) = &args[0] else {
+            return;
+        };
+
+        let namespace = match args.get(1).map(Argument::ident).transpose() {
+            Ok(namespace) => namespace,
+            Err(error) => return self.error(TypeError::Invalid { error, span }),
+        };
+
+        let directory = match self.importing.last() {
+            Some((_, path)) => Path::new(path).parent(),
+            None => self.path.as_deref().and_then(Path::parent),
+        };
+
+        let module = match Module::load(path, namespace, directory, self.io) {
+            Ok(module) => module,
+            Err(error) => return self.error(TypeError::Invalid { error, span }),
+        };
+
+        if let Some(start) = self.importing.iter().position(|(path, _)| *path == module.path) {
+            let error = RuntimeError::ImportCycle(
+                self.importing[start..]
+                    .iter()
+                    .map(|(_, path)| path.clone())
+                    .chain([module.source.path.clone()])
+                    .collect(),
+            );
+            return self.error(TypeError::Invalid { error, span });
+        }
+
+        if !self.imported.insert((module.path.clone(), module.namespace.clone())) {
+            return;
+        }
+
+        self.importing.push((module.path.clone(), module.source.path.clone()));
+        for invocation in module.program.iter() {
+            if invocation.name == ContextFunction::Fn.signature().name {
+                // Malformed definitions are reported when the module runs
+                if let Ok(function) = DefinedFunction::new(&invocation.args, Rc::default()) {
+                    self.define(function.signature());
+                }
+            } else if invocation.name == ContextFunction::Import.signature().name {
+                self.import(&invocation.args, span);
+            }
+        }
+        self.importing.pop();
+    }
+}
+
+/// The types of the arguments of `function`, by name
+fn parameters(function: &DefinedFunction) -> HashMap<String, ReturnType> {
+    function
+        .argument_names()
+        .iter()
+        .cloned()
+        .zip(function.signature().args.iter().map(|arg| match arg {
+            SignatureArgument::Data(data) => ReturnType::Data(*data),
+            _ => ReturnType::Any,
+        }))
+        .collect()
+}
+
+/// Whether `arg` is or contains a `return`
+fn contains_return(arg: &Argument) -> bool {
+    match arg {
+        Argument::Function(invocation) => {
+            invocation.name == SystemFunction::Return.signature().name
+                || invocation.args.iter().any(contains_return)
+        }
+        _ => false,
+    }
+}
+
+enum Match {
+    No,
+    /// Matches if the arguments typed [`ReturnType::Any`] have the right type at run time
+    Maybe,
+    Yes,
+}
+
+fn accepts(signature: &FunctionSignature, types: &[ReturnType]) -> Match {
+    let mut result = Match::Yes;
+
+    for (i, arg_type) in types.iter().enumerate() {
+        if let SignatureArgument::Data(expected) = signature.args[i.min(signature.args.len() - 1)] {
+            match arg_type {
+                ReturnType::Data(data) if *data != expected => return Match::No,
+                ReturnType::Any => result = Match::Maybe,
+                _ => {}
+            }
+        }
+    }
+
+    result
+}
@@ -1,6 +1,7 @@
-use std::mem;
+use std::collections::BTreeMap;
+use std::mem::{self, Discriminant};
 
-use super::{scope::{SignatureArgument, ReturnType}, Data, ControlFlow};
+use super::{scope::{SignatureArgument, ReturnType}, Data, ControlFlow, FunctionValue};
 
 macro_rules! signature_arg {
     ($name:ident, $arg:expr) => {
@@ -30,6 +31,8 @@ signature_arg!(arg_string, Data::String("".to_string()));
 signature_arg!(arg_number, Data::Number(0.));
 signature_arg!(arg_boolean, Data::Boolean(false));
 signature_arg!(arg_list, Data::List(Vec::new()));
+signature_arg!(arg_map, Data::Map(BTreeMap::new()));
+signature_arg!(arg_function, Data::Function(FunctionValue::default()));
 
 
 pub fn return_any() -> ReturnType {
@@ -40,5 +43,25 @@ return_type!(return_string, Data::String("".to_string()));
 return_type!(return_number, Data::Number(0.));
 return_type!(return_boolean, Data::Boolean(false));
 return_type!(return_list, Data::List(Vec::new()));
+return_type!(return_map, Data::Map(BTreeMap::new()));
+return_type!(return_function, Data::Function(FunctionValue::default()));
 return_type!(return_control, Data::ControlFlow(ControlFlow::Break));
-return_type!(return_unit, Data::Unit);
\ No newline at end of file
+return_type!(return_unit, Data::Unit);
+
+/// The name of the [`Data`] variant with the given discriminant
+pub fn type_name(discriminant: Discriminant<Data>) -> &'static str {
+    [
+        Data::String("".to_string()),
+        Data::Number(0.),
+        Data::Boolean(false),
+        Data::ControlFlow(ControlFlow::Break),
+        Data::List(Vec::new()),
+        Data::Map(BTreeMap::new()),
+        Data::Function(FunctionValue::default()),
+        Data::Unit,
+    ]
+    .iter()
+    .find(|data| mem::discriminant(*data) == discriminant)
+    .map(Data::type_name)
+    .unwrap_or("unknown")
+}
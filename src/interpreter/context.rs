@@ -1,36 +1,39 @@
 use std::cell::RefCell;
-use std::mem;
 use std::rc::Rc;
 
 use once_cell::sync::Lazy;
 use strum_macros::EnumIter;
 
 use crate::signature;
-use crate::util::OptionalStatic;
 
-use super::consts::{arg_any, arg_raw, return_unit, arg_boolean};
+use super::consts::{arg_any, arg_raw, return_any, return_unit, arg_boolean, arg_list, arg_string, return_function};
 use super::defined::DefinedFunction;
-use super::scope::{FunctionScope, FunctionSignature, SignatureArgument, VariableScope};
-use super::{Argument, Data, FunctionSource, ControlFlow};
+use super::function::FunctionValue;
+use super::runtime::Runtime;
+use super::scope::{FunctionSignature, SignatureArgument, VariableScope};
+use super::{Argument, Data, FunctionSource, ControlFlow, RuntimeError};
 
 /// A function definition that has access to the raw [`Argument`]s
 /// Should be used *only* for functions that require access to the raw [`Argument`]s
-#[derive(EnumIter, Debug, Clone)]
+#[derive(EnumIter, Debug, Clone, Copy)]
 pub enum ContextFunction {
     Let,
     If,
     Assign,
     While,
+    For,
     Fn,
+    Import,
+    Lambda,
 }
 
 /// The argument type for [`ContextFunction`]
-pub enum ContextArgument {
-    Raw(Argument),
+pub enum ContextArgument<'a> {
+    Raw(&'a Argument),
     Data(Rc<RefCell<Data>>),
 }
 
-impl ContextArgument {
+impl ContextArgument<'_> {
     fn data(&self) -> Rc<RefCell<Data>> {
         match self {
             Self::Data(data) => data.clone(),
@@ -50,74 +53,95 @@ impl ContextFunction {
     pub fn execute(
         &self,
         args: &[ContextArgument],
-        function_scope: &mut FunctionScope,
+        runtime: &mut Runtime,
         variable_scope: Rc<RefCell<VariableScope>>,
         global_scope: Rc<RefCell<VariableScope>>,
-    ) -> Rc<RefCell<Data>> {
-        Rc::new(RefCell::new(
+    ) -> Result<Rc<RefCell<Data>>, RuntimeError> {
+        Ok(Rc::new(RefCell::new(
             match self {
                 ContextFunction::Let => {
                     let evaluated = args[1].data();
                     variable_scope
                         .borrow_mut()
-                        .insert(args[0].raw().ident(), evaluated);
+                        .insert(args[0].raw().ident()?, evaluated);
                     Data::Unit
                 }
                 ContextFunction::If => {
-                    let mut iter = args.iter();
-                    if iter.next().unwrap().data().borrow().boolean() {
-                        let cloned_scope = Rc::new(RefCell::new(variable_scope.borrow().clone()));
-                        for invocation in iter {
-                            if let Data::ControlFlow(control) = invocation
-                                .raw()
-                                .eval(function_scope, cloned_scope.clone(), global_scope.clone())
+                    let Conditional { body, alternatives } =
+                        Conditional::new(args[1..].iter().map(ContextArgument::raw))?;
+
+                    let mut branch = args[0].data().borrow().boolean()?.then_some(body);
+                    for (condition, body) in alternatives {
+                        if branch.is_some() {
+                            break;
+                        }
+
+                        let taken = match condition {
+                            Some(condition) => condition
+                                .eval(runtime, variable_scope.clone(), global_scope.clone())?
                                 .borrow()
-                                .clone() {
-                                return Rc::new(RefCell::new(Data::ControlFlow(control)));
-                            }
+                                .boolean()?,
+                            None => true,
+                        };
+                        branch = taken.then_some(body);
+                    }
+
+                    // The value of the branch that ran is the value of its last invocation
+                    let mut last = Rc::new(RefCell::new(Data::Unit));
+                    let block_scope = VariableScope::child(variable_scope);
+                    for invocation in branch.unwrap_or_default() {
+                        last = invocation.eval(runtime, block_scope.clone(), global_scope.clone())?;
+                        if let Data::ControlFlow(_) = *last.borrow() {
+                            break;
                         }
                     }
-    
-                    Data::Unit
+
+                    return Ok(last);
                 }
                 ContextFunction::Assign => {
+                    let name = args[0].raw().ident()?;
+                    let data = args[1].data().borrow().clone();
                     *variable_scope
                         .borrow()
-                        .get(args[0].raw().ident().as_str())
-                        .expect("Variable not found")
-                        .borrow_mut() = args[1].data().borrow().clone();
+                        .get(name.as_str())
+                        .ok_or_else(|| RuntimeError::VariableNotFound(name).located(args[0].raw().span()))?
+                        .borrow_mut() = data;
                     Data::Unit
                 }
                 ContextFunction::While => {
                     let mut iter = args.iter();
-                    let predicate = iter.next().unwrap().raw().clone();
+                    let predicate = iter.next().unwrap().raw();
                     let body = iter.map(|e| e.raw()).collect::<Vec<_>>();
     
                     let mut continued = false;
     
                     while predicate
-                        .eval(function_scope, variable_scope.clone(), global_scope.clone())
+                        .eval(runtime, variable_scope.clone(), global_scope.clone())?
                         .borrow()
-                        .boolean()
+                        .boolean()?
                     {
+                        runtime.step()?;
                         if continued {
                             continued = false;
                             continue;
                         }
     
+                        let loop_scope = VariableScope::child(variable_scope.clone());
                         for &invocation in body.iter() {
                             if let Data::ControlFlow(control) = invocation
-                                .eval(function_scope, variable_scope.clone(), global_scope.clone())
+                                .eval(runtime, loop_scope.clone(), global_scope.clone())?
                                 .borrow()
                                 .clone()
                             {
                                 match control {
-                                    ControlFlow::Break => return Rc::new(RefCell::new(Data::Unit)),
+                                    ControlFlow::Break => return Ok(Rc::new(RefCell::new(Data::Unit))),
                                     ControlFlow::Continue => {
                                         continued = true;
                                         break;
                                     }
-                                    ControlFlow::Return(_) => return Rc::new(RefCell::new(Data::ControlFlow(control))),
+                                    ControlFlow::Return(_) | ControlFlow::TailCall(..) => {
+                                        return Ok(Rc::new(RefCell::new(Data::ControlFlow(control))))
+                                    }
                                 }
                             }
                         }
@@ -125,61 +149,174 @@ impl ContextFunction {
     
                     Data::Unit
                 }
+                ContextFunction::For => {
+                    let name = args[0].raw().ident()?;
+                    let list = args[1].data();
+                    let body = args[2..].iter().map(|e| e.raw()).collect::<Vec<_>>();
+
+                    // The list is indexed on every iteration, so elements pushed by the body are visited too
+                    let mut index = 0;
+                    loop {
+                        let element = list.borrow().element(index)?;
+                        let Some(element) = element else {
+                            break;
+                        };
+                        index += 1;
+                        runtime.step()?;
+
+                        // Every iteration binds the loop variable in a scope of its own
+                        let loop_scope = VariableScope::child(variable_scope.clone());
+                        loop_scope.borrow_mut().insert(name.clone(), Rc::new(RefCell::new(element)));
+
+                        for &invocation in body.iter() {
+                            if let Data::ControlFlow(control) = invocation
+                                .eval(runtime, loop_scope.clone(), global_scope.clone())?
+                                .borrow()
+                                .clone()
+                            {
+                                match control {
+                                    ControlFlow::Break => return Ok(Rc::new(RefCell::new(Data::Unit))),
+                                    ControlFlow::Continue => break,
+                                    ControlFlow::Return(_) | ControlFlow::TailCall(..) => {
+                                        return Ok(Rc::new(RefCell::new(Data::ControlFlow(control))))
+                                    }
+                                }
+                            }
+                        }
+                    }
+
+                    Data::Unit
+                }
                 ContextFunction::Fn => {
-                    function_scope.insert(FunctionSource::Defined(DefinedFunction::new(
+                    runtime.functions.insert(FunctionSource::Defined(DefinedFunction::new(
                         &args
                             .iter()
                             .map(|arg| arg.raw())
                             .cloned()
                             .collect::<Vec<_>>(),
                         global_scope,
-                    )));
+                    )?
+                    .in_module(runtime.module())));
+                    Data::Unit
+                }
+                ContextFunction::Import => {
+                    if args.len() > 2 {
+                        return Err(RuntimeError::InvalidArgument(
+                            "import takes a path and an optional namespace".into(),
+                        ));
+                    }
+
+                    let path = args[0].data().borrow().string()?.to_string();
+                    let namespace = args.get(1).map(|arg| arg.raw().ident()).transpose()?;
+                    runtime.import(&path, namespace)?;
                     Data::Unit
                 }
+                ContextFunction::Lambda => {
+                    let lambda = DefinedFunction::lambda(
+                        &args.iter().map(|arg| arg.raw()).cloned().collect::<Vec<_>>(),
+                        variable_scope,
+                    )?
+                    .in_module(runtime.module());
+                    Data::Function(FunctionValue::new(
+                        lambda.signature().name.clone(),
+                        vec![Rc::new(FunctionSource::Defined(lambda))],
+                    ))
+                }
             }
-        ))
+        )))
     }
 
-    pub fn signature(&self) -> OptionalStatic<FunctionSignature> {
+    pub fn signature(&self) -> &'static FunctionSignature {
         match self {
-            ContextFunction::Let => OptionalStatic::Static(&LET),
-            ContextFunction::If => OptionalStatic::Static(&IF),
-            ContextFunction::Assign => OptionalStatic::Static(&ASSIGN),
-            ContextFunction::While => OptionalStatic::Static(&WHILE),
-            ContextFunction::Fn => OptionalStatic::Static(&FN),
+            ContextFunction::Let => &LET,
+            ContextFunction::If => &IF,
+            ContextFunction::Assign => &ASSIGN,
+            ContextFunction::While => &WHILE,
+            ContextFunction::For => &FOR,
+            ContextFunction::Fn => &FN,
+            ContextFunction::Import => &IMPORT,
+            ContextFunction::Lambda => &LAMBDA,
+        }
+    }
+}
+
+/// The arguments of `if` after its condition
+pub(super) struct Conditional<'a> {
+    /// The invocations run when the condition is true
+    pub(super) body: Vec<&'a Argument>,
+    /// The `elif(condition, ...)` and `else(...)` branches that end the arguments, in order.
+    /// `else` has no condition and can only be the last
+    pub(super) alternatives: Vec<(Option<&'a Argument>, Vec<&'a Argument>)>,
+}
+
+impl<'a> Conditional<'a> {
+    pub(super) fn new(args: impl IntoIterator<Item = &'a Argument>) -> Result<Self, RuntimeError> {
+        let mut conditional = Self {
+            body: Vec::new(),
+            alternatives: Vec::new(),
+        };
+
+        for arg in args {
+            let alternative = match arg {
+                Argument::Function(invocation) if invocation.name == "elif" => match invocation.args.split_first() {
+                    Some((condition, body)) => Some((Some(condition), body.iter().collect())),
+                    None => return Err(malformed("`elif` without a condition").located(arg.span())),
+                },
+                Argument::Function(invocation) if invocation.name == "else" => Some((None, invocation.args.iter().collect())),
+                _ => None,
+            };
+
+            if matches!(conditional.alternatives.last(), Some((None, _))) {
+                return Err(malformed("`else` has to be the last branch").located(arg.span()));
+            }
+
+            match alternative {
+                Some(alternative) => conditional.alternatives.push(alternative),
+                None if conditional.alternatives.is_empty() => conditional.body.push(arg),
+                None => return Err(malformed("invocation after an `elif` or `else` branch").located(arg.span())),
+            }
         }
+
+        Ok(conditional)
     }
 }
 
+fn malformed(message: &str) -> RuntimeError {
+    RuntimeError::InvalidArgument(format!("malformed if: {message}"))
+}
+
 static LET: Lazy<FunctionSignature> = Lazy::new(|| signature!("let".into(), return_unit(), false, arg_raw(), arg_any()));
-static IF: Lazy<FunctionSignature> = Lazy::new(|| signature!("if".into(), return_unit(), true, arg_boolean(), arg_raw()));
+static IF: Lazy<FunctionSignature> = Lazy::new(|| signature!("if".into(), return_any(), true, arg_boolean(), arg_raw()));
 static ASSIGN: Lazy<FunctionSignature> = Lazy::new(|| signature!("=".into(), return_unit(), false, arg_raw(), arg_any()));
 static WHILE: Lazy<FunctionSignature> = Lazy::new(|| signature!("while".into(), return_unit(), true, arg_raw(), arg_raw()));
+static FOR: Lazy<FunctionSignature> = Lazy::new(|| signature!("for".into(), return_unit(), true, arg_raw(), arg_list(), arg_raw()));
 static FN: Lazy<FunctionSignature> = Lazy::new(|| signature!("fn".into(), return_unit(), true, arg_raw(), arg_raw()));
+static IMPORT: Lazy<FunctionSignature> = Lazy::new(|| signature!("import".into(), return_unit(), true, arg_string(), arg_raw()));
+static LAMBDA: Lazy<FunctionSignature> = Lazy::new(|| signature!("lambda".into(), return_function(), true, arg_raw()));
 
-pub fn to_context_args(
-    args: &[Argument],
+pub fn to_context_args<'a>(
+    args: &'a [Argument],
     signature: &FunctionSignature,
-    function_scope: &mut FunctionScope,
+    runtime: &mut Runtime,
     variable_scope: Rc<RefCell<VariableScope>>,
     global_scope: Rc<RefCell<VariableScope>>,
-) -> Vec<ContextArgument> {
+) -> Result<Vec<ContextArgument<'a>>, RuntimeError> {
     args.iter()
         .enumerate()
         .map(
-            |(i, arg)| match signature.args[i.min(signature.args.len() - 1)] {
+            |(i, arg)| Ok(match signature.args[i.min(signature.args.len() - 1)] {
                 SignatureArgument::Any => ContextArgument::Data(arg.eval(
-                    function_scope,
+                    runtime,
                     variable_scope.clone(),
                     global_scope.clone(),
-                )),
-                SignatureArgument::Raw => ContextArgument::Raw(arg.clone()),
+                )?),
+                SignatureArgument::Raw => ContextArgument::Raw(arg),
                 SignatureArgument::Data(_) => ContextArgument::Data(arg.eval(
-                    function_scope,
+                    runtime,
                     variable_scope.clone(),
                     global_scope.clone(),
-                )),
-            },
+                )?),
+            }),
         )
         .collect()
 }
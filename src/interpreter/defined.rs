@@ -1,54 +1,187 @@
 use std::{
     cell::RefCell,
+    collections::BTreeMap,
     mem::{self, Discriminant},
     rc::Rc,
 };
 
 use super::{
-    scope::{FunctionScope, FunctionSignature, SignatureArgument, VariableScope, ReturnType},
-    Argument, Data, Invocation, ControlFlow,
+    function::FunctionValue,
+    module::ModuleSource,
+    runtime::Runtime,
+    scope::{FunctionSignature, SignatureArgument, VariableScope, ReturnType},
+    Argument, Data, ControlFlow, RuntimeError,
 };
 
 /// A user defined function.
 #[derive(Debug, Clone)]
 pub struct DefinedFunction {
-    body: Vec<Invocation>,
+    /// Only invocations for functions defined with `fn`, while a lambda may also end with a plain value
+    body: Vec<Argument>,
     signature: FunctionSignature,
     scope: Rc<RefCell<VariableScope>>,
     argument_names: Vec<String>,
+    /// The module the function was defined in, if it was imported
+    module: Option<Rc<ModuleSource>>,
+    /// Whether the function is a lambda, which returns the value of its last invocation
+    lambda: bool,
 }
 
 impl DefinedFunction {
-    pub fn signature(&self) -> FunctionSignature {
-        self.signature.clone()
+    pub fn signature(&self) -> &FunctionSignature {
+        &self.signature
+    }
+
+    pub fn argument_names(&self) -> &[String] {
+        &self.argument_names
+    }
+
+    pub fn body(&self) -> &[Argument] {
+        &self.body
+    }
+
+    /// Mark the function as defined by the code of `module`
+    pub fn in_module(mut self, module: Option<Rc<ModuleSource>>) -> Self {
+        self.module = module;
+        self
+    }
+
+    /// Call the function as a value, with the scope it was defined in as its global scope
+    pub fn call(&self, args: &[Rc<RefCell<Data>>], runtime: &mut Runtime) -> Result<Rc<RefCell<Data>>, RuntimeError> {
+        self.execute(args, runtime, self.scope.clone())
     }
 
     pub fn execute(
         &self,
         args: &[Rc<RefCell<Data>>],
-        function_scope: &mut FunctionScope,
+        runtime: &mut Runtime,
         global_scope: Rc<RefCell<VariableScope>>,
-    ) -> Rc<RefCell<Data>> {
-        // Load arguments into scope
-        let scope = Rc::new(RefCell::new(self.scope.borrow().clone()));
+    ) -> Result<Rc<RefCell<Data>>, RuntimeError> {
+        runtime.enter()?;
+        let result = self.execute_tail_calls(args, runtime, global_scope);
+        runtime.leave();
+        result
+    }
+
+    /// Execute the body, then the body of each function it returns a tail call to in turn
+    fn execute_tail_calls(
+        &self,
+        args: &[Rc<RefCell<Data>>],
+        runtime: &mut Runtime,
+        global_scope: Rc<RefCell<VariableScope>>,
+    ) -> Result<Rc<RefCell<Data>>, RuntimeError> {
+        let mut tail: Option<FunctionValue> = None;
+        let mut args = args.to_vec();
+
+        loop {
+            let function = tail.as_ref().and_then(FunctionValue::defined).unwrap_or(self);
+            let result = function
+                .execute_body(&args, runtime, global_scope.clone())
+                .map_err(|error| match &function.module {
+                    Some(module) => error.in_module(module),
+                    None => error,
+                })?;
+
+            let call = match &*result.borrow() {
+                Data::ControlFlow(ControlFlow::TailCall(callee, args)) => Some((callee.clone(), args.clone())),
+                _ => None,
+            };
+            let Some((callee, callee_args)) = call else {
+                return Ok(result);
+            };
+
+            // The callee only checks its value against its own return type,
+            // so it runs in place of this function only if that covers this function's too
+            let return_type = &function.signature.return_type;
+            let covered = *return_type == ReturnType::Any
+                || callee.defined().is_some_and(|callee| callee.signature.return_type == *return_type);
+            if !covered {
+                let result = callee.call(&callee_args, runtime)?;
+                function.signature.check_return(&result.borrow())?;
+                return Ok(result);
+            }
+
+            tail = Some(callee);
+            args = callee_args;
+        }
+    }
+
+    fn execute_body(
+        &self,
+        args: &[Rc<RefCell<Data>>],
+        runtime: &mut Runtime,
+        global_scope: Rc<RefCell<VariableScope>>,
+    ) -> Result<Rc<RefCell<Data>>, RuntimeError> {
+        // Load arguments into a scope nested in the one the function was defined in
+        let scope = VariableScope::child(self.scope.clone());
         for (i, name) in self.argument_names.iter().enumerate() {
             scope.borrow_mut().insert(name.clone(), args[i].clone());
         }
 
         // Execute body
-        for invocation in self.body.iter() {
-            if let Data::ControlFlow(ControlFlow::Return(data)) = invocation.evaluate(function_scope, scope.clone(), global_scope.clone()).borrow().clone() {
-                return data;
+        let mut last = None;
+        for arg in self.body.iter() {
+            let result = arg.eval(runtime, scope.clone(), global_scope.clone())?;
+            if let Data::ControlFlow(ControlFlow::TailCall(..)) = *result.borrow() {
+                return Ok(result.clone());
+            }
+            if let Data::ControlFlow(ControlFlow::Return(data)) = result.borrow().clone() {
+                self.signature
+                    .check_return(&data.borrow())
+                    .map_err(|e| e.located(arg.span()))?;
+                return Ok(data);
+            }
+            last = Some(result);
+        }
+
+        if self.lambda {
+            if let Some(last) = last {
+                return Ok(last);
             }
         }
 
-        Rc::new(RefCell::new(Data::Unit))
+        // Falling off the end returns unit, which is only allowed for `void` functions
+        self.signature.check_return(&Data::Unit)?;
+        Ok(Rc::new(RefCell::new(Data::Unit)))
+    }
+
+    /// Parse the arguments of `lambda`: pairs of argument names and types followed by the body,
+    /// which may be plain values as well as invocations.
+    /// The lambda captures `scope`, which is usually the scope it is created in
+    pub fn lambda(arguments: &[Argument], scope: Rc<RefCell<VariableScope>>) -> Result<Self, RuntimeError> {
+        let mut argument_names = Vec::new();
+        let mut argument_types = Vec::new();
+
+        let mut rest = arguments;
+        while let [name @ Argument::Ident(..), arg_type @ Argument::Ident(..), _, ..] = rest {
+            argument_names.push(name.ident()?);
+            argument_types.push(str_to_data_discriminant(&arg_type.ident()?)?);
+            rest = &rest[2..];
+        }
+
+        if rest.is_empty() {
+            return Err(RuntimeError::InvalidArgument("malformed lambda: no body given".into()));
+        }
+
+        Ok(Self {
+            body: rest.to_vec(),
+            signature: FunctionSignature {
+                name: "lambda".into(),
+                args: argument_types.into_iter().map(SignatureArgument::Data).collect(),
+                repeating: false,
+                return_type: ReturnType::Any,
+            },
+            scope,
+            argument_names,
+            module: None,
+            lambda: true,
+        })
     }
 
-    pub fn new(arguments: &[Argument], global_scope: Rc<RefCell<VariableScope>>) -> Self {
+    pub fn new(arguments: &[Argument], global_scope: Rc<RefCell<VariableScope>>) -> Result<Self, RuntimeError> {
         let mut args = arguments.iter();
-        let name = args.next().expect("No function name given").ident();
-        let return_type = ReturnType::Data(str_to_data_discriminant(&args.next().expect("Malformed function").ident()));
+        let name = args.next().ok_or_else(|| malformed("no function name given"))?.ident()?;
+        let return_type = ReturnType::Data(str_to_data_discriminant(&args.next().ok_or_else(|| malformed("no return type given"))?.ident()?)?);
 
         let mut argument_names = Vec::new();
         let mut argument_types = Vec::new();
@@ -58,26 +191,25 @@ impl DefinedFunction {
         while let Some(arg) = args.next() {
             if in_signature {
                 if let Some(arg_type) = args.next() {
-                    if mem::discriminant(arg_type) == mem::discriminant(&Argument::Ident("".into()))
-                    {
-                        argument_names.push(arg.ident());
-                        argument_types.push(str_to_data_discriminant(&arg_type.ident()));
+                    if let Argument::Ident(..) = arg_type {
+                        argument_names.push(arg.ident()?);
+                        argument_types.push(str_to_data_discriminant(&arg_type.ident()?)?);
                     } else {
-                        body.push(arg.invocation());
-                        body.push(arg_type.invocation());
+                        body.push(Argument::Function(arg.invocation()?));
+                        body.push(Argument::Function(arg_type.invocation()?));
                         in_signature = false;
                     }
                 } else {
-                    body.push(arg.invocation());
+                    body.push(Argument::Function(arg.invocation()?));
                 }
 
                 continue;
             }
 
-            body.push(arg.invocation());
+            body.push(Argument::Function(arg.invocation()?));
         }
 
-        Self {
+        Ok(Self {
             body,
             signature: FunctionSignature {
                 name,
@@ -90,16 +222,25 @@ impl DefinedFunction {
             },
             scope: global_scope,
             argument_names,
-        }
+            module: None,
+            lambda: false,
+        })
     }
 }
 
-fn str_to_data_discriminant(string: &str) -> Discriminant<Data> {
-    match string {
+fn malformed(message: &str) -> RuntimeError {
+    RuntimeError::InvalidArgument(format!("malformed function definition: {message}"))
+}
+
+fn str_to_data_discriminant(string: &str) -> Result<Discriminant<Data>, RuntimeError> {
+    Ok(match string {
         "str" | "string" => mem::discriminant(&Data::String("".to_string())),
         "num" | "number" => mem::discriminant(&Data::Number(0.)),
         "bool" | "boolean" => mem::discriminant(&Data::Boolean(false)),
+        "list" => mem::discriminant(&Data::List(Vec::new())),
+        "map" => mem::discriminant(&Data::Map(BTreeMap::new())),
+        "func" | "function" => mem::discriminant(&Data::Function(FunctionValue::default())),
         "void" => mem::discriminant(&Data::Unit),
-        _ => panic!("Argument is not a recognised data type"),
-    }
+        _ => return Err(RuntimeError::UnknownType(string.to_string())),
+    })
 }
@@ -0,0 +1,333 @@
+use std::{error::Error, fmt, io, time::Duration};
+
+use pest::error::ErrorVariant;
+
+use crate::parser::{ParseError, Rule, Span};
+
+use std::rc::Rc;
+
+use super::{module::ModuleSource, scope::ReturnType};
+
+/// An error raised while executing a program
+#[derive(Debug, Clone, PartialEq)]
+pub enum RuntimeError {
+    /// No function with a matching name and signature is in scope
+    FunctionNotFound { name: String, args: Vec<ReturnType> },
+    /// No variable with the given name is in scope
+    VariableNotFound(String),
+    /// A value of the wrong type was given
+    TypeMismatch {
+        expected: &'static str,
+        found: &'static str,
+    },
+    /// A list was indexed outside of its bounds
+    IndexOutOfRange { index: f64, length: usize },
+    /// An element was taken from an empty list
+    EmptyList,
+    /// A map has no entry with the given key
+    KeyNotFound(String),
+    /// A user defined function returned a value of a different type than it declared
+    ReturnTypeMismatch {
+        name: String,
+        expected: ReturnType,
+        found: ReturnType,
+    },
+    /// A type name that does not exist was used in a function definition
+    UnknownType(String),
+    /// An argument had the right type but an unusable value
+    InvalidArgument(String),
+    /// Reading from or writing to the outside world failed
+    Io(String),
+    /// The program tried to do something its [`IoPolicy`](super::IoPolicy) does not allow
+    PermissionDenied(&'static str),
+    /// A module could not be read or parsed
+    ImportFailed { path: String, message: String },
+    /// A module imports itself, directly or through the modules it imports
+    ImportCycle(Vec<String>),
+    /// Calls were nested deeper than the configured maximum
+    StackOverflow(usize),
+    /// More invocations were evaluated than [`Limits::max_steps`](super::Limits::max_steps) allows
+    StepLimit(usize),
+    /// More list and map elements and string bytes were created than [`Limits::max_size`](super::Limits::max_size) allows
+    SizeLimit(usize),
+    /// The program ran for longer than [`Limits::timeout`](super::Limits::timeout) allows
+    Timeout(Duration),
+    /// An error raised by the invocation at `span`
+    Located { span: Span, error: Box<RuntimeError> },
+    /// An error raised by code in an imported module, whose spans refer to the module's source
+    InModule {
+        module: Rc<ModuleSource>,
+        error: Box<RuntimeError>,
+    },
+}
+
+impl RuntimeError {
+    /// Attach the location the error was raised at.
+    /// Errors that already have a location keep the innermost one
+    pub fn located(self, span: Span) -> Self {
+        match self {
+            RuntimeError::Located { .. } | RuntimeError::InModule { .. } => self,
+            error => RuntimeError::Located {
+                span,
+                error: Box::new(error),
+            },
+        }
+    }
+
+    /// Mark the error as raised by code in `module`.
+    /// Errors raised in a module imported by `module` keep the innermost one
+    pub(super) fn in_module(self, module: &Rc<ModuleSource>) -> Self {
+        match self {
+            RuntimeError::InModule { .. } => self,
+            error => RuntimeError::InModule {
+                module: module.clone(),
+                error: Box::new(error),
+            },
+        }
+    }
+
+    /// Where the error was raised in the source it was rendered against, if known
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            RuntimeError::Located { span, .. } => Some(*span),
+            _ => None,
+        }
+    }
+
+    /// The error without its location
+    pub fn kind(&self) -> &RuntimeError {
+        match self {
+            RuntimeError::Located { error, .. } | RuntimeError::InModule { error, .. } => error.kind(),
+            error => error,
+        }
+    }
+
+    /// Render the error against the `source` it was raised from, in the same format as parse errors
+    pub fn render(&self, source: &str, path: &str) -> String {
+        match self {
+            RuntimeError::InModule { module, error } => error.render(&module.source, &module.path),
+            _ => render_at(self.span(), &self.kind().to_string(), source, path),
+        }
+    }
+}
+
+/// Render `message` pointing at `span` in `source`, in the same format as parse errors
+fn render_at(span: Option<Span>, message: &str, source: &str, path: &str) -> String {
+    match span.and_then(|span| pest::Span::new(source, span.start, span.end)) {
+        Some(span) => pest::error::Error::<Rule>::new_from_span(
+            ErrorVariant::CustomError {
+                message: message.to_string(),
+            },
+            span,
+        )
+        .with_path(path)
+        .to_string(),
+        None => format!("{path}: {message}"),
+    }
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RuntimeError::FunctionNotFound { name, args } => write!(
+                f,
+                "function not found: {name}({})",
+                args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>().join(", ")
+            ),
+            RuntimeError::VariableNotFound(name) => write!(f, "variable not found: {name}"),
+            RuntimeError::TypeMismatch { expected, found } => {
+                write!(f, "type mismatch: expected {expected}, found {found}")
+            }
+            RuntimeError::IndexOutOfRange { index, length } => {
+                write!(f, "index {index} is out of range for a list of length {length}")
+            }
+            RuntimeError::EmptyList => write!(f, "the list is empty"),
+            RuntimeError::KeyNotFound(key) => write!(f, "key not found: {key}"),
+            RuntimeError::ReturnTypeMismatch {
+                name,
+                expected,
+                found,
+            } => write!(f, "function {name} is declared to return {expected} but returned {found}"),
+            RuntimeError::UnknownType(name) => write!(f, "unknown type: {name}"),
+            RuntimeError::InvalidArgument(message) => write!(f, "invalid argument: {message}"),
+            RuntimeError::Io(message) => write!(f, "io error: {message}"),
+            RuntimeError::PermissionDenied(action) => write!(f, "permission denied: the program may not {action}"),
+            // Parse errors span several lines and start on their own
+            RuntimeError::ImportFailed { path, message } => write!(f, "could not import {path}: {message}"),
+            RuntimeError::ImportCycle(paths) => write!(f, "import cycle: {}", paths.join(" -> ")),
+            RuntimeError::StackOverflow(depth) => write!(f, "stack overflow: calls nested more than {depth} deep"),
+            RuntimeError::StepLimit(steps) => write!(f, "step limit reached: evaluated more than {steps} invocations and loop iterations"),
+            RuntimeError::SizeLimit(size) => {
+                write!(f, "size limit reached: created more than {size} elements and bytes")
+            }
+            RuntimeError::Timeout(timeout) => write!(f, "timed out: ran for longer than {timeout:?}"),
+            RuntimeError::Located { span, error } => write!(f, "{span}: {error}"),
+            RuntimeError::InModule { module, error } => write!(f, "{}:{error}", module.path),
+        }
+    }
+}
+
+impl Error for RuntimeError {}
+
+/// An error raised while compiling a program to bytecode
+#[derive(Debug, Clone, PartialEq)]
+pub enum CompileError {
+    /// The construct can only be run by the tree-walking interpreter
+    Unsupported { construct: String, span: Span },
+    /// No function with a matching name and number of arguments is defined
+    FunctionNotFound { name: String, span: Span },
+    /// No variable with the given name is in scope
+    VariableNotFound { name: String, span: Span },
+    /// An argument is malformed, such as a `let` without a name
+    Invalid { error: RuntimeError, span: Span },
+}
+
+impl CompileError {
+    /// Where the error was raised
+    pub fn span(&self) -> Span {
+        match self {
+            CompileError::Unsupported { span, .. }
+            | CompileError::FunctionNotFound { span, .. }
+            | CompileError::VariableNotFound { span, .. }
+            | CompileError::Invalid { span, .. } => *span,
+        }
+    }
+
+    /// Render the error against the `source` it was raised from, in the same format as parse errors
+    pub fn render(&self, source: &str, path: &str) -> String {
+        render_at(Some(self.span()), &self.to_string(), source, path)
+    }
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CompileError::Unsupported { construct, .. } => {
+                write!(f, "{construct} is not supported by the bytecode compiler")
+            }
+            CompileError::FunctionNotFound { name, .. } => write!(f, "function not found: {name}"),
+            CompileError::VariableNotFound { name, .. } => write!(f, "variable not found: {name}"),
+            CompileError::Invalid { error, .. } => write!(f, "{}", error.kind()),
+        }
+    }
+}
+
+impl Error for CompileError {}
+
+/// An error found by [`check`](super::check) before a program runs
+#[derive(Debug, Clone, PartialEq)]
+pub enum TypeError {
+    /// No function with a matching name and signature is defined for the argument types
+    FunctionNotFound {
+        name: String,
+        args: Vec<ReturnType>,
+        span: Span,
+    },
+    /// No variable with the given name is in scope
+    VariableNotFound { name: String, span: Span },
+    /// A value of the wrong type is used where only one type is allowed
+    TypeMismatch {
+        expected: ReturnType,
+        found: ReturnType,
+        span: Span,
+    },
+    /// A function returns a value of a different type than it declared
+    ReturnTypeMismatch {
+        name: String,
+        expected: ReturnType,
+        found: ReturnType,
+        span: Span,
+    },
+    /// A function with a return type other than `void` can never return a value
+    MissingReturn { name: String, expected: ReturnType, span: Span },
+    /// An argument is malformed, such as a `let` without a name
+    Invalid { error: RuntimeError, span: Span },
+}
+
+impl TypeError {
+    /// Where the error was found
+    pub fn span(&self) -> Span {
+        match self {
+            TypeError::FunctionNotFound { span, .. }
+            | TypeError::VariableNotFound { span, .. }
+            | TypeError::TypeMismatch { span, .. }
+            | TypeError::ReturnTypeMismatch { span, .. }
+            | TypeError::MissingReturn { span, .. }
+            | TypeError::Invalid { span, .. } => *span,
+        }
+    }
+
+    /// Render the error against the `source` it was found in, in the same format as parse errors
+    pub fn render(&self, source: &str, path: &str) -> String {
+        render_at(Some(self.span()), &self.to_string(), source, path)
+    }
+}
+
+impl fmt::Display for TypeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TypeError::FunctionNotFound { name, args, .. } => write!(
+                f,
+                "function not found: {name}({})",
+                args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>().join(", ")
+            ),
+            TypeError::VariableNotFound { name, .. } => write!(f, "variable not found: {name}"),
+            TypeError::TypeMismatch { expected, found, .. } => {
+                write!(f, "type mismatch: expected {expected}, found {found}")
+            }
+            TypeError::ReturnTypeMismatch {
+                name,
+                expected,
+                found,
+                ..
+            } => write!(f, "function {name} is declared to return {expected} but returns {found}"),
+            TypeError::MissingReturn { name, expected, .. } => {
+                write!(f, "function {name} is declared to return {expected} but never returns")
+            }
+            TypeError::Invalid { error, .. } => write!(f, "{}", error.kind()),
+        }
+    }
+}
+
+impl Error for TypeError {}
+
+/// An error raised while loading and executing source code with an [`Interpreter`](super::Interpreter)
+#[derive(Debug)]
+pub enum EvalError {
+    /// The source file could not be read
+    Io(io::Error),
+    /// The source code is not valid syntax
+    Parse(ParseError),
+    /// The program failed while running
+    Runtime(RuntimeError),
+}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EvalError::Io(e) => write!(f, "could not read source: {e}"),
+            EvalError::Parse(e) => write!(f, "{e}"),
+            EvalError::Runtime(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl Error for EvalError {}
+
+impl From<io::Error> for EvalError {
+    fn from(value: io::Error) -> Self {
+        EvalError::Io(value)
+    }
+}
+
+impl From<ParseError> for EvalError {
+    fn from(value: ParseError) -> Self {
+        EvalError::Parse(value)
+    }
+}
+
+impl From<RuntimeError> for EvalError {
+    fn from(value: RuntimeError) -> Self {
+        EvalError::Runtime(value)
+    }
+}
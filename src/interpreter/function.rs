@@ -0,0 +1,81 @@
+use std::{cell::RefCell, fmt, mem, rc::Rc};
+
+use super::{defined::DefinedFunction, runtime::Runtime, scope::ReturnType, Data, FunctionSource, RuntimeError};
+
+/// A function held as [`Data`], such as a lambda or a named function passed as an argument
+#[derive(Debug, Clone, Default)]
+pub struct FunctionValue {
+    name: String,
+    /// The overloads that existed when the value was made, tried in order
+    overloads: Rc<[Rc<FunctionSource>]>,
+}
+
+impl FunctionValue {
+    pub(super) fn new(name: String, overloads: Vec<Rc<FunctionSource>>) -> Self {
+        Self {
+            name,
+            overloads: overloads.into(),
+        }
+    }
+
+    /// The named function `name`, if any overload of it can be called with evaluated arguments
+    pub(super) fn named(name: &str, candidates: &[Rc<FunctionSource>]) -> Option<Self> {
+        let overloads = candidates
+            .iter()
+            .filter(|function| !matches!(***function, FunctionSource::Context(_)))
+            .cloned()
+            .collect::<Vec<_>>();
+
+        (!overloads.is_empty()).then(|| Self::new(name.to_string(), overloads))
+    }
+
+    /// The user defined function this value refers to, if it has a single overload that is one
+    pub(super) fn defined(&self) -> Option<&DefinedFunction> {
+        match &*self.overloads {
+            [function] => match &**function {
+                FunctionSource::Defined(function) => Some(function),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Call the first overload accepting `args`
+    pub fn call(
+        &self,
+        args: &[Rc<RefCell<Data>>],
+        runtime: &mut Runtime,
+    ) -> Result<Rc<RefCell<Data>>, RuntimeError> {
+        match self
+            .overloads
+            .iter()
+            .find(|function| function.signature().accepts_data(args))
+        {
+            Some(function) => function.call(args, runtime),
+            None => Err(RuntimeError::FunctionNotFound {
+                name: self.name.clone(),
+                args: args
+                    .iter()
+                    .map(|arg| ReturnType::Data(mem::discriminant(&*arg.borrow())))
+                    .collect(),
+            }),
+        }
+    }
+}
+
+/// Function values are equal when they refer to the same functions
+impl PartialEq for FunctionValue {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.overloads, &other.overloads)
+    }
+}
+
+impl fmt::Display for FunctionValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<function {}>", self.name)
+    }
+}
@@ -0,0 +1,157 @@
+use std::{
+    cell::RefCell,
+    fs,
+    io::{BufRead, Write},
+    path::Path,
+    rc::Rc,
+};
+
+use crate::parser::{self, Span};
+
+use super::{
+    bytecode::{self, Program},
+    check,
+    native::NativeFunction,
+    policy::IoPolicy,
+    runtime::{Limits, Runtime},
+    scope::{self, FunctionSignature, VariableScope},
+    Argument, CompileError, Data, EvalError, FunctionSource, Invocation, RuntimeError, TypeError,
+};
+
+/// An interpreter that keeps its functions and global variables between evaluations.
+/// This is the entry point for programs embedding Func#
+pub struct Interpreter {
+    runtime: Runtime,
+    globals: Rc<RefCell<VariableScope>>,
+}
+
+impl Interpreter {
+    pub fn new() -> Self {
+        Self {
+            runtime: Runtime::default(),
+            globals: Rc::new(RefCell::new(scope::default_variable_scope())),
+        }
+    }
+
+    /// Execute already parsed invocations, returning the result of the last one
+    pub fn evaluate(&mut self, program: &[Invocation]) -> Result<Rc<RefCell<Data>>, RuntimeError> {
+        self.runtime.start();
+        let mut result = Rc::new(RefCell::new(Data::Unit));
+
+        for invocation in program.iter() {
+            result = invocation.evaluate(&mut self.runtime, self.globals.clone(), self.globals.clone())?;
+        }
+
+        Ok(result)
+    }
+
+    /// Parse and execute a program, returning the result of its last invocation
+    pub fn eval_str(&mut self, source: &str) -> Result<Rc<RefCell<Data>>, EvalError> {
+        let program = parser::parse_program(source)?;
+        Ok(self.evaluate(&program)?)
+    }
+
+    /// Read, parse and execute the program at `path`, returning the result of its last invocation.
+    /// Imports in the program are resolved relative to it
+    pub fn eval_file(&mut self, path: impl AsRef<Path>) -> Result<Rc<RefCell<Data>>, EvalError> {
+        let source = fs::read_to_string(path.as_ref())?;
+
+        let previous = self.runtime.path.replace(path.as_ref().to_path_buf());
+        let result = self.eval_str(&source);
+        self.runtime.path = previous;
+
+        result
+    }
+
+    /// Resolve imports in the programs evaluated and checked from now on relative to the file at `path`
+    pub fn set_path(&mut self, path: impl AsRef<Path>) {
+        self.runtime.path = Some(path.as_ref().to_path_buf());
+    }
+
+    /// Set the resources each evaluation may use before it is stopped with an error
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.runtime.set_limits(limits);
+    }
+
+    /// Set the side effects programs may have, and where their output and input go
+    pub fn set_io_policy(&mut self, io: IoPolicy) {
+        self.runtime.set_io_policy(io);
+    }
+
+    /// Send what programs print to `output`, allowing them to write it.
+    /// Give it a [`Capture`](super::Capture) to read the output back
+    pub fn set_output(&mut self, output: impl Write + 'static) {
+        self.runtime.set_output(output);
+    }
+
+    /// Read the input of programs from `input`, allowing them to read it
+    pub fn set_input(&mut self, input: impl BufRead + 'static) {
+        self.runtime.set_input(input);
+    }
+
+    /// Call the function `name` with already evaluated arguments.
+    /// Errors raised by the call itself have no location, since it is not written in any source
+    pub fn call(&mut self, name: &str, args: Vec<Data>) -> Result<Rc<RefCell<Data>>, RuntimeError> {
+        let invocation = Invocation {
+            name: name.to_string(),
+            args: args.into_iter().map(|arg| Argument::Data(arg, Span::default())).collect(),
+            span: Span::default(),
+            overload: Default::default(),
+        };
+
+        self.runtime.start();
+        invocation
+            .evaluate(&mut self.runtime, self.globals.clone(), self.globals.clone())
+            .map_err(|error| match error {
+                RuntimeError::Located { span, error } if span == Span::default() => *error,
+                error => error,
+            })
+    }
+
+    /// Type check a program against the functions and global variables defined on this interpreter,
+    /// reporting every error found without running it
+    pub fn check(&self, program: &[Invocation]) -> Result<(), Vec<TypeError>> {
+        check::check_with(
+            program,
+            &self.runtime.functions,
+            &self.globals.borrow(),
+            self.runtime.path.as_deref(),
+            &self.runtime.io,
+        )
+    }
+
+    /// Compile a program to bytecode, binding calls to the functions defined on this interpreter.
+    /// The compiled program has its own variables and runs independently with [`Program::run`]
+    pub fn compile(&self, program: &[Invocation]) -> Result<Program, CompileError> {
+        bytecode::compile_with(program, &self.runtime.functions)
+    }
+
+    /// Make a host function callable from scripts under the name and argument types of `signature`
+    pub fn register(
+        &mut self,
+        signature: FunctionSignature,
+        function: impl Fn(&[Rc<RefCell<Data>>]) -> Result<Data, RuntimeError> + 'static,
+    ) {
+        self.runtime
+            .functions
+            .insert(FunctionSource::Native(NativeFunction::new(signature, function)));
+    }
+
+    /// Get a global variable
+    pub fn get(&self, name: &str) -> Option<Rc<RefCell<Data>>> {
+        self.globals.borrow().get(name)
+    }
+
+    /// Define or overwrite a global variable
+    pub fn set(&mut self, name: &str, value: Data) {
+        self.globals
+            .borrow_mut()
+            .insert(name.to_string(), Rc::new(RefCell::new(value)));
+    }
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,120 @@
+use std::{
+    collections::HashSet,
+    fs,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+use pest::error::LineColLocation;
+
+use crate::parser;
+
+use super::{context::ContextFunction, policy::IoPolicy, Argument, Invocation, RuntimeError};
+
+/// The source code of an imported file, kept to render errors raised by its functions
+#[derive(Debug, PartialEq)]
+pub struct ModuleSource {
+    /// The path the file was imported with, joined to the directory of the file importing it
+    pub path: String,
+    pub source: String,
+}
+
+/// A parsed file brought in by `import`
+pub(super) struct Module {
+    /// The canonical path of the file, which identifies it when looking for cycles
+    pub(super) path: PathBuf,
+    /// The prefix of every function the module defines, without the `::`
+    pub(super) namespace: String,
+    pub(super) source: Rc<ModuleSource>,
+    pub(super) program: Vec<Invocation>,
+}
+
+impl Module {
+    /// Read and parse the module at `path`, relative to `directory` if given, if `io` allows it.
+    /// Calls to the functions it defines are renamed to `namespace::name`,
+    /// defaulting to the file name without its extension
+    pub(super) fn load(
+        path: &str,
+        namespace: Option<String>,
+        directory: Option<&Path>,
+        io: &IoPolicy,
+    ) -> Result<Self, RuntimeError> {
+        let failed = |message: String| RuntimeError::ImportFailed {
+            path: path.to_string(),
+            message,
+        };
+
+        let resolved = match directory {
+            Some(directory) => directory.join(path),
+            None => PathBuf::from(path),
+        };
+        let display = resolved.display().to_string();
+        let resolved = io.import_path(&resolved).map_err(|e| match e {
+            RuntimeError::Io(message) => failed(message),
+            e => e,
+        })?;
+
+        let namespace = match namespace {
+            Some(namespace) => namespace,
+            None => resolved
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+                .ok_or_else(|| failed("the path has no file name to use as a namespace".into()))?,
+        };
+
+        let source = fs::read_to_string(&resolved).map_err(|e| failed(e.to_string()))?;
+        // Only the position of a syntax error is reported, so that importing a file that is not a module
+        // does not reveal what it contains
+        let mut program = parser::parse_program(&source).map_err(|e| {
+            let (line, column) = match e.line_col {
+                LineColLocation::Pos(position) | LineColLocation::Span(position, _) => position,
+            };
+            failed(format!("{display}:{line}:{column}: {}", e.variant.message()))
+        })?;
+
+        let defined = program
+            .iter()
+            .filter(|invocation| invocation.name == ContextFunction::Fn.signature().name)
+            .filter_map(|invocation| match invocation.args.first() {
+                Some(Argument::Ident(name, _)) => Some(name.clone()),
+                _ => None,
+            })
+            .collect::<HashSet<_>>();
+
+        for invocation in program.iter_mut() {
+            qualify(invocation, &defined, &namespace);
+        }
+
+        Ok(Self {
+            path: resolved,
+            namespace,
+            source: Rc::new(ModuleSource {
+                path: display,
+                source,
+            }),
+            program,
+        })
+    }
+}
+
+/// Prefix every definition of, call to and use as a value of a function in `defined` with `namespace::`
+fn qualify(invocation: &mut Invocation, defined: &HashSet<String>, namespace: &str) {
+    if defined.contains(&invocation.name) {
+        invocation.name = format!("{namespace}::{}", invocation.name);
+    }
+
+    // The names of variables being bound are not references to functions
+    let binds = invocation.name == ContextFunction::Let.signature().name
+        || invocation.name == ContextFunction::Assign.signature().name
+        || invocation.name == ContextFunction::For.signature().name;
+
+    for (i, arg) in invocation.args.iter_mut().enumerate() {
+        match arg {
+            Argument::Function(invocation) => qualify(invocation, defined, namespace),
+            Argument::Ident(name, _) if defined.contains(name) && !(binds && i == 0) => {
+                *name = format!("{namespace}::{name}");
+            }
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,42 @@
+use std::{cell::RefCell, fmt, rc::Rc};
+
+use super::{scope::FunctionSignature, Data, RuntimeError};
+
+/// The closure type backing a [`NativeFunction`]
+pub type NativeClosure = dyn Fn(&[Rc<RefCell<Data>>]) -> Result<Data, RuntimeError>;
+
+/// A function registered by the host program.
+/// Like a [`SystemFunction`](super::system::SystemFunction) it only sees the evaluated [`Data`]
+#[derive(Clone)]
+pub struct NativeFunction {
+    signature: FunctionSignature,
+    function: Rc<NativeClosure>,
+}
+
+impl NativeFunction {
+    pub fn new(
+        signature: FunctionSignature,
+        function: impl Fn(&[Rc<RefCell<Data>>]) -> Result<Data, RuntimeError> + 'static,
+    ) -> Self {
+        Self {
+            signature,
+            function: Rc::new(function),
+        }
+    }
+
+    pub fn signature(&self) -> &FunctionSignature {
+        &self.signature
+    }
+
+    pub fn execute(&self, args: &[Rc<RefCell<Data>>]) -> Result<Rc<RefCell<Data>>, RuntimeError> {
+        Ok(Rc::new(RefCell::new((self.function)(args)?)))
+    }
+}
+
+impl fmt::Debug for NativeFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NativeFunction")
+            .field("signature", &self.signature)
+            .finish_non_exhaustive()
+    }
+}
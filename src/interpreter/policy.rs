@@ -0,0 +1,212 @@
+use std::{
+    cell::RefCell,
+    env, fmt, fs,
+    io::{self, BufRead, Write},
+    path::{Component, Path, PathBuf},
+    rc::Rc,
+};
+
+use super::RuntimeError;
+
+/// Where input is read from
+enum Input {
+    /// The stdin of the process, shared with anything else reading it
+    Stdin,
+    Reader(Box<dyn BufRead>),
+}
+
+/// Which files `import` may read
+enum Imports {
+    Anywhere,
+    /// Only files inside this directory, once symbolic links are followed
+    Within(PathBuf),
+}
+
+/// The side effects a program is allowed to have, and where its output and input go.
+/// A program can only use the capabilities it has been given: builtins that need one it lacks
+/// raise [`RuntimeError::PermissionDenied`]. By default the process's stdout and stdin are used
+pub struct IoPolicy {
+    /// Where `println` writes, if it may
+    output: Option<Box<dyn Write>>,
+    /// Where `stdin` reads from, if it may
+    input: Option<Input>,
+    /// The files `import` reads, if it may
+    imports: Option<Imports>,
+}
+
+impl IoPolicy {
+    /// A policy that allows no input or output at all
+    pub fn deny_all() -> Self {
+        Self {
+            output: None,
+            input: None,
+            imports: None,
+        }
+    }
+
+    /// Write output to `output` instead
+    pub fn with_output(mut self, output: impl Write + 'static) -> Self {
+        self.output = Some(Box::new(output));
+        self
+    }
+
+    /// Read input from `input` instead
+    pub fn with_input(mut self, input: impl BufRead + 'static) -> Self {
+        self.input = Some(Input::Reader(Box::new(input)));
+        self
+    }
+
+    /// Only let `import` read files inside the directory `root`
+    pub fn with_imports_within(mut self, root: impl Into<PathBuf>) -> Self {
+        self.imports = Some(Imports::Within(root.into()));
+        self
+    }
+
+    /// Forbid reading files with `import`
+    pub fn deny_imports(mut self) -> Self {
+        self.imports = None;
+        self
+    }
+
+    /// Forbid writing output
+    pub fn deny_output(mut self) -> Self {
+        self.output = None;
+        self
+    }
+
+    /// Forbid reading input
+    pub fn deny_input(mut self) -> Self {
+        self.input = None;
+        self
+    }
+
+    pub fn allows_output(&self) -> bool {
+        self.output.is_some()
+    }
+
+    pub fn allows_input(&self) -> bool {
+        self.input.is_some()
+    }
+
+    pub fn allows_imports(&self) -> bool {
+        self.imports.is_some()
+    }
+
+    /// The canonical path of the file at `path`, failing unless `import` may read it.
+    /// Paths outside the module root are refused before anything on disk is looked at
+    pub(super) fn import_path(&self, path: &Path) -> Result<PathBuf, RuntimeError> {
+        let denied = RuntimeError::PermissionDenied("import files outside its module root");
+        let root = match &self.imports {
+            None => return Err(RuntimeError::PermissionDenied("import files")),
+            Some(Imports::Anywhere) => return fs::canonicalize(path).map_err(|e| RuntimeError::Io(e.to_string())),
+            Some(Imports::Within(root)) => root,
+        };
+
+        if !normalize(path).starts_with(normalize(root)) {
+            return Err(denied);
+        }
+
+        // Symbolic links inside the root may still lead out of it
+        let root = fs::canonicalize(root).map_err(|e| RuntimeError::Io(e.to_string()))?;
+        let path = fs::canonicalize(path).map_err(|e| RuntimeError::Io(e.to_string()))?;
+        if !path.starts_with(root) {
+            return Err(denied);
+        }
+
+        Ok(path)
+    }
+
+    /// Write `line` followed by a newline to the output
+    pub(super) fn write_line(&mut self, line: &str) -> Result<(), RuntimeError> {
+        let output = self
+            .output
+            .as_mut()
+            .ok_or(RuntimeError::PermissionDenied("write output"))?;
+        writeln!(output, "{line}").map_err(|e| RuntimeError::Io(e.to_string()))
+    }
+
+    /// Read a line from the input, including its newline.
+    /// The line is empty once the input is exhausted
+    pub(super) fn read_line(&mut self) -> Result<String, RuntimeError> {
+        let mut line = String::new();
+        match self.input.as_mut().ok_or(RuntimeError::PermissionDenied("read input"))? {
+            Input::Stdin => io::stdin().read_line(&mut line),
+            Input::Reader(reader) => reader.read_line(&mut line),
+        }
+        .map_err(|e| RuntimeError::Io(e.to_string()))?;
+        Ok(line)
+    }
+}
+
+impl Default for IoPolicy {
+    fn default() -> Self {
+        Self {
+            output: Some(Box::new(io::stdout())),
+            input: Some(Input::Stdin),
+            imports: Some(Imports::Anywhere),
+        }
+    }
+}
+
+/// Output collected in memory, for reading back what a program printed.
+/// Clones share the same buffer, so one can be given to an [`IoPolicy`] and another kept to read from
+#[derive(Debug, Clone, Default)]
+pub struct Capture(Rc<RefCell<Vec<u8>>>);
+
+impl Capture {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Everything written so far, with invalid UTF-8 replaced
+    pub fn contents(&self) -> String {
+        String::from_utf8_lossy(&self.0.borrow()).into_owned()
+    }
+
+    /// Take everything written so far, leaving the buffer empty
+    pub fn take(&self) -> String {
+        let bytes = self.0.take();
+        String::from_utf8_lossy(&bytes).into_owned()
+    }
+}
+
+impl Write for Capture {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl fmt::Debug for IoPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("IoPolicy")
+            .field("output", &self.allows_output())
+            .field("input", &self.allows_input())
+            .field("imports", &self.allows_imports())
+            .finish()
+    }
+}
+
+/// `path` made absolute with `.` and `..` resolved, without looking at the file system
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = match path.is_absolute() {
+        true => PathBuf::new(),
+        false => env::current_dir().unwrap_or_default(),
+    };
+
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+
+    normalized
+}
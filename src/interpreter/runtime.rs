@@ -0,0 +1,256 @@
+use std::{
+    cell::RefCell,
+    collections::HashSet,
+    hint,
+    io::{BufRead, Write},
+    mem,
+    path::{Path, PathBuf},
+    rc::Rc,
+    time::{Duration, Instant},
+};
+
+use super::{
+    module::{Module, ModuleSource},
+    policy::IoPolicy,
+    scope::{self, FunctionScope},
+    RuntimeError,
+};
+
+/// The number of calls that can be nested unless [`Limits::max_depth`] says otherwise
+pub const DEFAULT_MAX_DEPTH: usize = 1000;
+
+/// The most native stack a single call uses, with room to spare for unoptimized builds
+const STACK_PER_CALL: usize = 64 * 1024;
+
+/// The bytes of native stack calls can use unless [`Limits::max_stack`] says otherwise,
+/// which is enough for [`DEFAULT_MAX_DEPTH`] calls
+pub const DEFAULT_MAX_STACK: usize = DEFAULT_MAX_DEPTH * STACK_PER_CALL;
+
+/// The stack a thread needs beyond [`Limits::max_stack`], for the host and for builtins called at the deepest point
+const STACK_HEADROOM: usize = 1024 * 1024;
+
+/// How many steps are taken between checks of the deadline, since reading the clock is slow
+const DEADLINE_INTERVAL: usize = 1024;
+
+/// The resources a program may use before it is stopped with an error, for running programs that are not trusted.
+/// Only the nesting of calls is limited by default, and programs have to run on a thread with a stack of
+/// [`Limits::stack_size`] for that limit to be reached before the thread runs out of stack
+#[derive(Debug, Clone, PartialEq)]
+pub struct Limits {
+    /// The most calls that can be nested before raising [`RuntimeError::StackOverflow`]
+    pub max_depth: usize,
+    /// The most bytes of native stack nested calls can use before raising [`RuntimeError::StackOverflow`],
+    /// so that deep recursion fails cleanly however large the stack of the thread running it is
+    pub max_stack: usize,
+    /// The most invocations and loop iterations that can be evaluated before raising [`RuntimeError::StepLimit`]
+    pub max_steps: Option<usize>,
+    /// The most list and map elements and string bytes a program can create in total before raising
+    /// [`RuntimeError::SizeLimit`]. Values that are no longer used still count, so this bounds the memory
+    /// a program can take however it spreads it across values
+    pub max_size: Option<usize>,
+    /// How long a program can run before raising [`RuntimeError::Timeout`]
+    pub timeout: Option<Duration>,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_depth: DEFAULT_MAX_DEPTH,
+            max_stack: DEFAULT_MAX_STACK,
+            max_steps: None,
+            max_size: None,
+            timeout: None,
+        }
+    }
+}
+
+impl Limits {
+    /// Allow calls to nest `max_depth` deep, with enough stack for that many even in unoptimized builds.
+    /// The program then has to run on a thread with a stack of [`Limits::stack_size`]
+    pub fn with_max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth;
+        self.max_stack = max_depth.saturating_mul(STACK_PER_CALL);
+        self
+    }
+
+    /// The stack a thread running programs under these limits needs
+    pub fn stack_size(&self) -> usize {
+        self.max_stack.saturating_add(STACK_HEADROOM)
+    }
+}
+
+/// The state shared by everything a running program calls
+#[derive(Default)]
+pub struct Runtime {
+    pub(super) functions: FunctionScope,
+    /// The file the program was read from, which imports are resolved against
+    pub(super) path: Option<PathBuf>,
+    /// Modules whose top level is being evaluated, innermost last
+    importing: Vec<(PathBuf, Rc<ModuleSource>)>,
+    /// Modules that have been imported, with the namespace they were imported under
+    imported: HashSet<(PathBuf, String)>,
+    /// The number of calls to user defined functions currently being executed
+    depth: usize,
+    /// Where the native stack was when the outermost call started
+    stack_base: usize,
+    /// The resources the program may use
+    limits: Limits,
+    /// The number of steps taken since the program started
+    steps: usize,
+    /// The number of list and map elements and string bytes created since the program started
+    allocated: usize,
+    /// When the program has to have finished by
+    deadline: Option<Instant>,
+    /// The side effects the program may have
+    pub(super) io: IoPolicy,
+}
+
+impl Runtime {
+    /// Every function that can be called
+    pub fn functions(&self) -> &FunctionScope {
+        &self.functions
+    }
+
+    pub fn limits(&self) -> &Limits {
+        &self.limits
+    }
+
+    /// Set the resources programs started from now on may use
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    /// Set the side effects programs may have, and where their output and input go
+    pub fn set_io_policy(&mut self, io: IoPolicy) {
+        self.io = io;
+    }
+
+    /// Send what programs print to `output`, allowing them to write it
+    pub fn set_output(&mut self, output: impl Write + 'static) {
+        self.io = mem::take(&mut self.io).with_output(output);
+    }
+
+    /// Read the input of programs from `input`, allowing them to read it
+    pub fn set_input(&mut self, input: impl BufRead + 'static) {
+        self.io = mem::take(&mut self.io).with_input(input);
+    }
+
+    /// Reset the step count and start the clock for a program that is about to run
+    pub fn start(&mut self) {
+        self.steps = 0;
+        self.allocated = 0;
+        self.deadline = self.limits.timeout.and_then(|timeout| Instant::now().checked_add(timeout));
+    }
+
+    /// Count an invocation or loop iteration, failing once the program has taken too many steps or too long
+    pub(super) fn step(&mut self) -> Result<(), RuntimeError> {
+        self.steps += 1;
+
+        if let Some(max_steps) = self.limits.max_steps {
+            if self.steps > max_steps {
+                return Err(RuntimeError::StepLimit(max_steps));
+            }
+        }
+
+        if let (Some(deadline), Some(timeout)) = (self.deadline, self.limits.timeout) {
+            if self.steps.is_multiple_of(DEADLINE_INTERVAL) && Instant::now() >= deadline {
+                return Err(RuntimeError::Timeout(timeout));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Fail if creating `size` more elements or bytes would take the program over its limit
+    pub(super) fn check_size(&self, size: usize) -> Result<(), RuntimeError> {
+        match self.limits.max_size {
+            Some(max_size) if self.allocated.saturating_add(size) > max_size => Err(RuntimeError::SizeLimit(max_size)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Count `size` elements or bytes that have been created, failing once the program has created too many
+    pub(super) fn allocate(&mut self, size: usize) -> Result<(), RuntimeError> {
+        self.check_size(size)?;
+        self.allocated += size;
+        Ok(())
+    }
+
+    /// Count a call that is starting, failing if it would nest too deep or use too much of the native stack
+    pub(super) fn enter(&mut self) -> Result<(), RuntimeError> {
+        if self.depth >= self.limits.max_depth {
+            return Err(RuntimeError::StackOverflow(self.limits.max_depth));
+        }
+
+        let position = stack_position();
+        if self.depth == 0 {
+            self.stack_base = position;
+        } else if self.stack_base.abs_diff(position) > self.limits.max_stack {
+            return Err(RuntimeError::StackOverflow(self.limits.max_depth));
+        }
+
+        self.depth += 1;
+        Ok(())
+    }
+
+    /// Count a call that has finished, whether or not it succeeded
+    pub(super) fn leave(&mut self) {
+        self.depth -= 1;
+    }
+
+    /// Whether a user defined function is being executed
+    pub(super) fn in_function(&self) -> bool {
+        self.depth > 0
+    }
+
+    /// The directory relative imports are resolved against
+    fn directory(&self) -> Option<&Path> {
+        match self.importing.last() {
+            Some((_, source)) => Path::new(&source.path).parent(),
+            None => self.path.as_deref().and_then(Path::parent),
+        }
+    }
+
+    /// The module whose top level is being evaluated, if any
+    pub(super) fn module(&self) -> Option<Rc<ModuleSource>> {
+        self.importing.last().map(|(_, source)| source.clone())
+    }
+
+    /// Evaluate the module at `path` in its own global scope, defining its functions under `namespace`.
+    /// Importing a module again under the same namespace does nothing
+    pub(super) fn import(&mut self, path: &str, namespace: Option<String>) -> Result<(), RuntimeError> {
+        let module = Module::load(path, namespace, self.directory(), &self.io)?;
+
+        if let Some(start) = self.importing.iter().position(|(path, _)| *path == module.path) {
+            return Err(RuntimeError::ImportCycle(
+                self.importing[start..]
+                    .iter()
+                    .map(|(_, source)| source.path.clone())
+                    .chain([module.source.path.clone()])
+                    .collect(),
+            ));
+        }
+
+        if !self.imported.insert((module.path.clone(), module.namespace.clone())) {
+            return Ok(());
+        }
+
+        let globals = Rc::new(RefCell::new(scope::default_variable_scope()));
+        self.importing.push((module.path.clone(), module.source.clone()));
+        let result = module.program.iter().try_for_each(|invocation| {
+            invocation
+                .evaluate(self, globals.clone(), globals.clone())
+                .map(drop)
+        });
+        self.importing.pop();
+
+        result.map_err(|error| error.in_module(&module.source))
+    }
+}
+
+/// The address of a local variable, which tells how deep into the native stack the caller is
+#[inline(never)]
+fn stack_position() -> usize {
+    let marker = 0u8;
+    hint::black_box(&marker) as *const u8 as usize
+}
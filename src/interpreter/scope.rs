@@ -1,11 +1,13 @@
-use std::mem::Discriminant;
-use std::{cell::RefCell, collections::HashMap, rc::Rc};
+use std::mem::{self, Discriminant};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::{cell::RefCell, collections::HashMap, fmt, rc::Rc};
 
 use strum::IntoEnumIterator;
 
+use super::consts::type_name;
 use super::context::ContextFunction;
 use super::{system::SystemFunction, Data, FunctionSource};
-use super::{Argument, ControlFlow};
+use super::{Argument, Invocation, RuntimeError};
 
 #[derive(Debug, Clone)]
 /// The signature of a [`FunctionSource`]
@@ -30,92 +32,292 @@ pub enum ReturnType {
     Any,
 }
 
-/// Contains a list of all defined functions
+impl fmt::Display for ReturnType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReturnType::Data(data) => write!(f, "{}", type_name(*data)),
+            ReturnType::Any => write!(f, "any"),
+        }
+    }
+}
+
+impl FunctionSignature {
+    /// Whether this signature can be called with `count` arguments.
+    /// The last argument of a repeating signature may be given any number of times (including zero)
+    pub fn accepts_count(&self, count: usize) -> bool {
+        if self.repeating {
+            count + 1 >= self.args.len()
+        } else {
+            count == self.args.len()
+        }
+    }
+
+    /// Whether this signature accepts already evaluated arguments
+    pub fn accepts_data(&self, args: &[Rc<RefCell<Data>>]) -> bool {
+        self.accepts_count(args.len())
+            && args.iter().enumerate().all(|(i, arg)| {
+                match &self.args[i.min(self.args.len() - 1)] {
+                    SignatureArgument::Data(data) => mem::discriminant(&*arg.borrow()) == *data,
+                    _ => true,
+                }
+            })
+    }
+
+    /// Check that `data` has the type this signature declares it returns
+    pub fn check_return(&self, data: &Data) -> Result<(), RuntimeError> {
+        let found = ReturnType::Data(mem::discriminant(data));
+
+        match &self.return_type {
+            ReturnType::Data(_) if found != self.return_type => Err(RuntimeError::ReturnTypeMismatch {
+                name: self.name.clone(),
+                expected: self.return_type.clone(),
+                found,
+            }),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Contains all defined functions, indexed by name.
+/// Functions sharing a name are overloads and are tried in the order they were defined
 pub struct FunctionScope {
-    scope: Vec<FunctionSource>,
+    scope: HashMap<String, Vec<Rc<FunctionSource>>>,
+    /// Changes whenever a function is defined, invalidating every [`OverloadCache`] made before
+    version: u64,
 }
 
-// Contains all defined Variables
-pub type VariableScope = HashMap<String, Rc<RefCell<Data>>>;
+/// The variables declared directly in one block, such as a function body or the body of `if`,
+/// along with the scope of the block it is nested in
+#[derive(Debug, Default)]
+pub struct VariableScope {
+    variables: HashMap<String, Rc<RefCell<Data>>>,
+    parent: Option<Rc<RefCell<VariableScope>>>,
+}
+
+impl VariableScope {
+    /// An empty scope nested in `parent`, which can see and assign every variable `parent` can
+    pub fn child(parent: Rc<RefCell<VariableScope>>) -> Rc<RefCell<Self>> {
+        Rc::new(RefCell::new(Self {
+            variables: HashMap::new(),
+            parent: Some(parent),
+        }))
+    }
+
+    /// The variable `name` from the innermost scope that declares it
+    pub fn get(&self, name: &str) -> Option<Rc<RefCell<Data>>> {
+        match self.variables.get(name) {
+            Some(variable) => Some(variable.clone()),
+            None => self.parent.as_ref()?.borrow().get(name),
+        }
+    }
+
+    /// Declare `name` in this scope, shadowing any variable of the same name in the scopes around it
+    pub fn insert(&mut self, name: String, value: Rc<RefCell<Data>>) {
+        self.variables.insert(name, value);
+    }
+
+    /// The variables declared directly in this scope
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Rc<RefCell<Data>>)> {
+        self.variables.iter()
+    }
+}
+
+/// The overload an [`Invocation`] resolved to the last time it was evaluated
+#[derive(Debug, Clone)]
+pub struct OverloadCache {
+    version: u64,
+    /// The argument types that were inspected to pick the overload and may differ the next time
+    types: Vec<Option<ReturnType>>,
+    function: Rc<FunctionSource>,
+}
+
+static NEXT_VERSION: AtomicU64 = AtomicU64::new(0);
+
+fn next_version() -> u64 {
+    NEXT_VERSION.fetch_add(1, Ordering::Relaxed)
+}
 
 impl FunctionScope {
+    /// Find the function called by `invocation`.
+    ///
+    /// Argument types are only worked out when an overload needs them and at most once per argument.
+    /// Resolution only depends on the types it inspects, so the result is cached on the invocation
+    /// and reused for as long as those types (and the defined functions) stay the same.
+    /// Only the types of variables and of calls whose type depends on their own arguments are checked again
     pub fn get(
         &self,
-        name: &str,
+        invocation: &Invocation,
+        variable_scope: Rc<RefCell<VariableScope>>,
+    ) -> Result<Option<Rc<FunctionSource>>, RuntimeError> {
+        if let Some(cache) = invocation.overload.borrow().as_ref() {
+            if cache.version == self.version
+                && self.cache_valid(cache, &invocation.args, variable_scope.clone())?
+            {
+                return Ok(Some(cache.function.clone()));
+            }
+        }
+
+        let Some(candidates) = self.scope.get(&invocation.name) else {
+            return Ok(None);
+        };
+
+        let mut types = vec![None; invocation.args.len()];
+        for function in candidates.iter() {
+            if self.matches(function.signature(), &invocation.args, &mut types, variable_scope.clone())? {
+                for (arg_type, arg) in types.iter_mut().zip(invocation.args.iter()) {
+                    if self.fixed_type(arg) {
+                        *arg_type = None;
+                    }
+                }
+
+                *invocation.overload.borrow_mut() = Some(OverloadCache {
+                    version: self.version,
+                    types,
+                    function: function.clone(),
+                });
+                return Ok(Some(function.clone()));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// The type every overload `invocation` could resolve to returns, if they all agree
+    fn common_return_type(&self, invocation: &Invocation) -> Option<&ReturnType> {
+        let mut candidates = self
+            .scope
+            .get(&invocation.name)?
+            .iter()
+            .map(|function| function.signature())
+            .filter(|signature| signature.accepts_count(invocation.args.len()));
+
+        let first = candidates.next()?;
+        candidates
+            .all(|signature| signature.return_type == first.return_type)
+            .then_some(&first.return_type)
+    }
+
+    /// Whether the type of `arg` is the same every time it is evaluated, for as long as the defined functions are
+    fn fixed_type(&self, arg: &Argument) -> bool {
+        match arg {
+            Argument::Data(..) => true,
+            Argument::Function(invocation) => self.common_return_type(invocation).is_some(),
+            Argument::Ident(..) => false,
+        }
+    }
+
+    /// The type `invocation` returns without evaluating it.
+    /// When every overload with the right number of arguments agrees there is no need to resolve it
+    pub fn return_type(
+        &self,
+        invocation: &Invocation,
+        variable_scope: Rc<RefCell<VariableScope>>,
+    ) -> Result<ReturnType, RuntimeError> {
+        if let Some(return_type) = self.common_return_type(invocation) {
+            return Ok(return_type.clone());
+        }
+
+        match self.get(invocation, variable_scope.clone())? {
+            Some(function) => Ok(function.signature().return_type.clone()),
+            // A variable holding a function can be called like one, but what it returns is only known once it has
+            None if variable_scope
+                .borrow()
+                .get(&invocation.name)
+                .is_some_and(|data| matches!(*data.borrow(), Data::Function(_))) =>
+            {
+                Ok(ReturnType::Any)
+            }
+            None => Err(invocation.not_found(self, variable_scope)),
+        }
+    }
+
+    fn cache_valid(
+        &self,
+        cache: &OverloadCache,
         args: &[Argument],
-        function_scope: &FunctionScope,
         variable_scope: Rc<RefCell<VariableScope>>,
-    ) -> Option<&FunctionSource> {
-        self.scope.iter().find(|&function| {
-            if function.signature().get_ref().name != name {
-                return false;
+    ) -> Result<bool, RuntimeError> {
+        for (arg, cached) in args.iter().zip(cache.types.iter()) {
+            if let Some(cached) = cached {
+                if arg.return_type(self, variable_scope.clone())? != *cached {
+                    return Ok(false);
+                }
             }
+        }
+
+        Ok(true)
+    }
 
-            let signature = function.signature();
+    fn matches(
+        &self,
+        signature: &FunctionSignature,
+        args: &[Argument],
+        types: &mut [Option<ReturnType>],
+        variable_scope: Rc<RefCell<VariableScope>>,
+    ) -> Result<bool, RuntimeError> {
+        if !signature.accepts_count(args.len()) {
+            return Ok(false);
+        }
+
+        for (i, arg) in args.iter().enumerate() {
+            let corresponding = if signature.repeating {
+                &signature.args[i.min(signature.args.len() - 1)]
+            } else {
+                &signature.args[i]
+            };
 
-            for (i, arg) in args.iter().enumerate() {
-                let corresponding = if signature.get_ref().repeating {
-                    Some(&signature.get_ref().args[i.min(signature.get_ref().args.len() - 1)])
-                } else {
-                    signature.get_ref().args.get(i)
+            if let SignatureArgument::Data(data) = corresponding {
+                let arg_type = match &types[i] {
+                    Some(arg_type) => arg_type.clone(),
+                    None => types[i]
+                        .insert(arg.return_type(self, variable_scope.clone())?)
+                        .clone(),
                 };
 
-                if let Some(corresponding) = corresponding {
-                    if !match corresponding {
-                        SignatureArgument::Data(data) => {
-                            match arg.return_type(function_scope, variable_scope.clone()) {
-                                ReturnType::Data(value) => value == *data,
-                                ReturnType::Any => true,
-                            }
-                        }
-                        _ => true,
-                    } {
-                        return false;
-                    }
-                } else {
-                    return false;
+                match arg_type {
+                    ReturnType::Data(value) if value != *data => return Ok(false),
+                    _ => {}
                 }
             }
+        }
+
+        Ok(true)
+    }
 
-            true
-        })
+    /// Every overload defined under `name`, in the order they are tried
+    pub fn candidates(&self, name: &str) -> &[Rc<FunctionSource>] {
+        self.scope.get(name).map(Vec::as_slice).unwrap_or_default()
     }
 
     pub fn insert(&mut self, function: FunctionSource) {
-        self.scope.push(function);
+        self.scope
+            .entry(function.signature().name.clone())
+            .or_default()
+            .push(Rc::new(function));
+        self.version = next_version();
     }
 }
 
 impl Default for FunctionScope {
     fn default() -> Self {
-        let mut scope = Vec::new();
+        let mut scope = Self {
+            scope: HashMap::new(),
+            version: next_version(),
+        };
 
         for function in SystemFunction::iter() {
-            scope.push(FunctionSource::System(function));
+            scope.insert(FunctionSource::System(function));
         }
 
         for function in ContextFunction::iter() {
-            scope.push(FunctionSource::Context(function));
+            scope.insert(FunctionSource::Context(function));
         }
 
-        Self { scope }
+        scope
     }
 }
 
-/// Generate default variable scope
+/// Generate default variable scope, which is empty now that `true`, `false`, `break` and `continue` are literals
 pub fn default_variable_scope() -> VariableScope {
-    let mut scope = HashMap::new();
-
-    scope.insert("true".into(), Rc::new(RefCell::new(Data::Boolean(true))));
-    scope.insert("false".into(), Rc::new(RefCell::new(Data::Boolean(false))));
-    scope.insert(
-        "break".into(),
-        Rc::new(RefCell::new(Data::ControlFlow(ControlFlow::Break))),
-    );
-    scope.insert(
-        "continue".into(),
-        Rc::new(RefCell::new(Data::ControlFlow(ControlFlow::Continue))),
-    );
-
-    scope
+    VariableScope::default()
 }
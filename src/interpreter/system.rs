@@ -1,15 +1,15 @@
-use std::io::stdin;
-use std::{cell::RefCell, rc::Rc};
+use std::{cell::RefCell, collections::BTreeMap, fmt::{self, Write}, rc::Rc, slice};
 
 use once_cell::sync::Lazy;
 use strum_macros::EnumIter;
 
 use crate::signature;
-use crate::util::OptionalStatic;
 
-use super::consts::{arg_string, arg_any, return_string, return_number, return_boolean, return_unit, return_control, arg_number, arg_boolean, arg_list, return_any, return_list};
-use super::scope::{FunctionScope, FunctionSignature, VariableScope};
-use super::{Data, ControlFlow};
+use super::consts::{arg_string, arg_any, return_string, return_number, return_boolean, return_unit, return_control, arg_number, arg_boolean, arg_list, return_any, return_list, arg_function, arg_map, return_map};
+use super::function::FunctionValue;
+use super::runtime::Runtime;
+use super::scope::{FunctionSignature, VariableScope};
+use super::{Data, ControlFlow, RuntimeError};
 
 /// A function that has the same power as a user defined function but is hard-coded.
 /// This means it does not have access to the raw [`Argument`](super::Argument) but rather the parsed [`Data`]
@@ -31,6 +31,7 @@ pub enum SystemFunction {
     Break,
     Continue,
     Return,
+    ReturnUnit,
     Cmp,
     GreaterThan,
     GreaterThanOrEqual,
@@ -41,86 +42,426 @@ pub enum SystemFunction {
     Index,
     Length,
     List,
+    Range,
+    RangeStep,
+    Call,
+    Map,
+    Filter,
+    Fold,
+    Reduce,
+    Sort,
+    SortBy,
+    Any,
+    All,
+    Find,
+    Zip,
+    Enumerate,
+    Reverse,
+    Slice,
+    Concat,
+    NewMap,
+    Get,
+    Set,
+    Has,
+    Remove,
+    Keys,
+    Values,
+    Entries,
+    StringConcat,
+    StringLength,
+    Substring,
+    StringSlice,
+    Split,
+    Join,
+    Replace,
+    Contains,
+    StartsWith,
+    EndsWith,
+    Upper,
+    Lower,
+    CharAt,
+    Format,
+    ToString,
 }
 
 impl SystemFunction {
     pub fn execute(
         &self,
         args: &[Rc<RefCell<Data>>],
-        function_scope: &mut FunctionScope,
-        variable_scope: Rc<RefCell<VariableScope>>,
-    ) -> Rc<RefCell<Data>> {
-        Rc::new(RefCell::new(
+        runtime: &mut Runtime,
+        _variable_scope: Rc<RefCell<VariableScope>>,
+    ) -> Result<Rc<RefCell<Data>>, RuntimeError> {
+        let data = Rc::new(RefCell::new(
             match self {
-                SystemFunction::Stdin => Data::String({
-                    let mut string = String::new();
-                    stdin().read_line(&mut string).unwrap();
-                    string
-                }),
-                SystemFunction::Number => Data::Number(args[0].borrow().to_string().parse::<f64>().unwrap()),
+                SystemFunction::Stdin => Data::String(runtime.io.read_line()?),
+                SystemFunction::Number => {
+                    let string = args[0].borrow().to_string();
+                    Data::Number(string.parse::<f64>().map_err(|_| RuntimeError::InvalidArgument(format!("`{string}` is not a number")))?)
+                },
                 SystemFunction::Trim => Data::String(args[0].borrow().to_string().trim().into()),
-                SystemFunction::Not => Data::Boolean(!args[0].borrow().boolean()),
-                SystemFunction::And => Data::Boolean(args.iter().all(|arg| arg.borrow().boolean())),
-                SystemFunction::Or => Data::Boolean(args.iter().any(|arg| arg.borrow().boolean())),
-                SystemFunction::Xor => Data::Boolean(args.iter().fold(0, |acc, arg| acc + arg.borrow().boolean() as usize) == 1),
-                SystemFunction::Add => operator_impl(|acc, arg| acc + arg.borrow().number(), args),
-                SystemFunction::Sub => operator_impl(|acc, arg| acc - arg.borrow().number(), args),
-                SystemFunction::Mul => operator_impl(|acc, arg| acc * arg.borrow().number(), args),
-                SystemFunction::Div => operator_impl(|acc, arg| acc / arg.borrow().number(), args),
-                SystemFunction::Mod => operator_impl(|acc, arg| acc % arg.borrow().number(), args),
+                SystemFunction::Not => Data::Boolean(!args[0].borrow().boolean()?),
+                SystemFunction::And => Data::Boolean(boolean_args(args)?.iter().all(|&b| b)),
+                SystemFunction::Or => Data::Boolean(boolean_args(args)?.iter().any(|&b| b)),
+                SystemFunction::Xor => Data::Boolean(boolean_args(args)?.iter().filter(|&&b| b).count() == 1),
+                SystemFunction::Add => operator_impl(|acc, arg| acc + arg, args)?,
+                SystemFunction::Sub => operator_impl(|acc, arg| acc - arg, args)?,
+                SystemFunction::Mul => operator_impl(|acc, arg| acc * arg, args)?,
+                SystemFunction::Div => operator_impl(|acc, arg| acc / arg, args)?,
+                SystemFunction::Mod => operator_impl(|acc, arg| acc % arg, args)?,
                 SystemFunction::Println => {
-                    println!("{}", args.iter().map(|e| e.borrow().to_string()).collect::<Vec<_>>().join(" "));
+                    let line = join(args, " ", runtime)?;
+                    runtime.io.write_line(&line)?;
                     Data::Unit
                 },
                 SystemFunction::Break => Data::ControlFlow(ControlFlow::Break),
                 SystemFunction::Continue => Data::ControlFlow(ControlFlow::Continue),
                 SystemFunction::Return => Data::ControlFlow(ControlFlow::Return(args[0].clone())),
-                SystemFunction::Cmp => Data::Boolean(args[0].borrow().clone() == args[1].borrow().clone()),
-                SystemFunction::GreaterThan => Data::Boolean(args[0].borrow().number() > args[1].borrow().number()),
-                SystemFunction::GreaterThanOrEqual => Data::Boolean(args[0].borrow().number() >= args[1].borrow().number()),
-                SystemFunction::LessThan => Data::Boolean(args[0].borrow().number() < args[1].borrow().number()),
-                SystemFunction::LessThanOrEqual => Data::Boolean(args[0].borrow().number() <= args[1].borrow().number()),
+                SystemFunction::ReturnUnit => Data::ControlFlow(ControlFlow::Return(Rc::new(RefCell::new(Data::Unit)))),
+                SystemFunction::Cmp => Data::Boolean(*args[0].borrow() == *args[1].borrow()),
+                SystemFunction::GreaterThan => Data::Boolean(args[0].borrow().number()? > args[1].borrow().number()?),
+                SystemFunction::GreaterThanOrEqual => Data::Boolean(args[0].borrow().number()? >= args[1].borrow().number()?),
+                SystemFunction::LessThan => Data::Boolean(args[0].borrow().number()? < args[1].borrow().number()?),
+                SystemFunction::LessThanOrEqual => Data::Boolean(args[0].borrow().number()? <= args[1].borrow().number()?),
                 SystemFunction::Push => {
+                    runtime.allocate(args.len() - 1)?;
                     let mut iter = args.iter();
-                    iter.next().unwrap().borrow_mut().list_mut().append(&mut iter.cloned().collect::<Vec<_>>());
+                    iter.next().unwrap().borrow_mut().list_mut()?.append(&mut iter.cloned().collect::<Vec<_>>());
                     Data::Unit
                 },
-                SystemFunction::Pop => return args[0].borrow_mut().list_mut().pop().unwrap(),
-                SystemFunction::Index => return args[0].borrow_mut().list().get(args[1].borrow().number() as usize).unwrap().clone(),
-                SystemFunction::Length => Data::Number(args[0].borrow_mut().list_mut().len() as f64),
+                SystemFunction::Pop => return args[0].borrow_mut().list_mut()?.pop().ok_or(RuntimeError::EmptyList),
+                SystemFunction::Index => {
+                    let list = args[0].borrow().list()?;
+                    let index = args[1].borrow().number()?;
+                    return list_index(&list, index)
+                        .cloned()
+                        .ok_or(RuntimeError::IndexOutOfRange { index, length: list.len() });
+                },
+                SystemFunction::Length => Data::Number(args[0].borrow().list()?.len() as f64),
                 SystemFunction::List => Data::List(args.to_vec()),
+                SystemFunction::Range | SystemFunction::RangeStep => {
+                    let start = args[0].borrow().number()?;
+                    let end = args[1].borrow().number()?;
+                    let step = match args.get(2) {
+                        Some(step) => step.borrow().number()?,
+                        None => 1.,
+                    };
+
+                    let count = ((end - start) / step).ceil().max(0.);
+                    if step == 0. || !count.is_finite() {
+                        return Err(RuntimeError::InvalidArgument(format!(
+                            "range from {start} to {end} by {step} does not end"
+                        )));
+                    }
+                    let count = count as usize;
+                    runtime.check_size(count)?;
+
+                    let mut list = Vec::new();
+                    if list.try_reserve_exact(count).is_err() {
+                        return Err(RuntimeError::InvalidArgument(format!(
+                            "range from {start} to {end} by {step} has too many elements to create"
+                        )));
+                    }
+                    list.extend((0..count).map(|i| Rc::new(RefCell::new(Data::Number(start + i as f64 * step)))));
+                    Data::List(list)
+                }
+                SystemFunction::Call => {
+                    let function = args[0].borrow().function()?;
+                    return function.call(&args[1..], runtime);
+                }
+                SystemFunction::Map => {
+                    // The list is copied out first, since the callback may use it too
+                    let function = args[1].borrow().function()?;
+                    let list = args[0].borrow().list()?;
+                    Data::List(
+                        list.into_iter()
+                            .map(|element| function.call(&[element], runtime))
+                            .collect::<Result<_, _>>()?,
+                    )
+                }
+                SystemFunction::Filter => {
+                    let function = args[1].borrow().function()?;
+                    let list = args[0].borrow().list()?;
+                    let mut filtered = Vec::new();
+                    for element in list {
+                        if predicate(&function, &element, runtime)? {
+                            filtered.push(element);
+                        }
+                    }
+                    Data::List(filtered)
+                }
+                SystemFunction::Fold => {
+                    let function = args[2].borrow().function()?;
+                    let list = args[0].borrow().list()?;
+                    return list
+                        .into_iter()
+                        .try_fold(args[1].clone(), |acc, element| function.call(&[acc, element], runtime));
+                }
+                SystemFunction::Reduce => {
+                    let function = args[1].borrow().function()?;
+                    let mut list = args[0].borrow().list()?.into_iter();
+                    let first = list.next().ok_or(RuntimeError::EmptyList)?;
+                    return list.try_fold(first, |acc, element| function.call(&[acc, element], runtime));
+                }
+                SystemFunction::Sort => {
+                    let list = args[0].borrow().list()?;
+                    Data::List(merge_sort(list, &mut |a, b| less_than(&a.borrow(), &b.borrow()))?)
+                }
+                SystemFunction::SortBy => {
+                    let function = args[1].borrow().function()?;
+                    let list = args[0].borrow().list()?;
+                    Data::List(merge_sort(list, &mut |a, b| {
+                        function.call(&[a.clone(), b.clone()], runtime)?.borrow().boolean()
+                    })?)
+                }
+                SystemFunction::Any => {
+                    let function = args[1].borrow().function()?;
+                    let list = args[0].borrow().list()?;
+                    let mut found = false;
+                    for element in list {
+                        if predicate(&function, &element, runtime)? {
+                            found = true;
+                            break;
+                        }
+                    }
+                    Data::Boolean(found)
+                }
+                SystemFunction::All => {
+                    let function = args[1].borrow().function()?;
+                    let list = args[0].borrow().list()?;
+                    let mut all = true;
+                    for element in list {
+                        if !predicate(&function, &element, runtime)? {
+                            all = false;
+                            break;
+                        }
+                    }
+                    Data::Boolean(all)
+                }
+                SystemFunction::Find => {
+                    let function = args[1].borrow().function()?;
+                    let list = args[0].borrow().list()?;
+                    for element in list {
+                        if predicate(&function, &element, runtime)? {
+                            return Ok(element);
+                        }
+                    }
+                    Data::Unit
+                }
+                SystemFunction::Zip => Data::List(
+                    args[0]
+                        .borrow()
+                        .list()?
+                        .into_iter()
+                        .zip(args[1].borrow().list()?)
+                        .map(|(a, b)| Rc::new(RefCell::new(Data::List(vec![a, b]))))
+                        .collect(),
+                ),
+                SystemFunction::Enumerate => Data::List(
+                    args[0]
+                        .borrow()
+                        .list()?
+                        .into_iter()
+                        .enumerate()
+                        .map(|(i, element)| {
+                            let index = Rc::new(RefCell::new(Data::Number(i as f64)));
+                            Rc::new(RefCell::new(Data::List(vec![index, element])))
+                        })
+                        .collect(),
+                ),
+                SystemFunction::Reverse => Data::List(args[0].borrow().list()?.into_iter().rev().collect()),
+                SystemFunction::Slice => {
+                    let list = args[0].borrow().list()?;
+                    let (start, end) = range(&args[1..], list.len())?;
+                    Data::List(list[start..end].to_vec())
+                }
+                SystemFunction::Concat => Data::List(
+                    args.iter()
+                        .map(|arg| arg.borrow().list())
+                        .collect::<Result<Vec<_>, _>>()?
+                        .concat(),
+                ),
+                SystemFunction::NewMap => {
+                    if !args.len().is_multiple_of(2) {
+                        return Err(RuntimeError::InvalidArgument("map takes pairs of keys and values".into()));
+                    }
+                    let mut map = BTreeMap::new();
+                    for pair in args.chunks(2) {
+                        map.insert(pair[0].borrow().string()?.to_string(), pair[1].clone());
+                    }
+                    Data::Map(map)
+                }
+                SystemFunction::Get => {
+                    let key = args[1].borrow().string()?.to_string();
+                    return args[0].borrow().map()?.get(&key).cloned().ok_or(RuntimeError::KeyNotFound(key));
+                }
+                SystemFunction::Set => {
+                    let key = args[1].borrow().string()?.to_string();
+                    runtime.allocate(1)?;
+                    args[0].borrow_mut().map_mut()?.insert(key, args[2].clone());
+                    Data::Unit
+                }
+                SystemFunction::Has => Data::Boolean(args[0].borrow().map()?.contains_key(args[1].borrow().string()?)),
+                SystemFunction::Remove => {
+                    let key = args[1].borrow().string()?.to_string();
+                    return args[0].borrow_mut().map_mut()?.remove(&key).ok_or(RuntimeError::KeyNotFound(key));
+                }
+                SystemFunction::Keys => Data::List(
+                    args[0]
+                        .borrow()
+                        .map()?
+                        .keys()
+                        .map(|key| Rc::new(RefCell::new(Data::String(key.clone()))))
+                        .collect(),
+                ),
+                SystemFunction::Values => Data::List(args[0].borrow().map()?.values().cloned().collect()),
+                SystemFunction::Entries => Data::List(
+                    args[0]
+                        .borrow()
+                        .map()?
+                        .iter()
+                        .map(|(key, value)| {
+                            let key = Rc::new(RefCell::new(Data::String(key.clone())));
+                            Rc::new(RefCell::new(Data::List(vec![key, value.clone()])))
+                        })
+                        .collect(),
+                ),
+                SystemFunction::StringConcat => {
+                    let mut length = 0usize;
+                    for arg in args {
+                        length = length.saturating_add(arg.borrow().string()?.len());
+                    }
+                    runtime.check_size(length)?;
+
+                    Data::String(
+                        args.iter()
+                            .map(|arg| Ok(arg.borrow().string()?.to_string()))
+                            .collect::<Result<String, RuntimeError>>()?,
+                    )
+                }
+                SystemFunction::StringLength => Data::Number(args[0].borrow().string()?.chars().count() as f64),
+                SystemFunction::Substring | SystemFunction::StringSlice => {
+                    let string = args[0].borrow().string()?.chars().collect::<Vec<_>>();
+                    let (start, end) = range(&args[1..], string.len())?;
+                    Data::String(string[start..end].iter().collect())
+                }
+                SystemFunction::Split => {
+                    let string = args[0].borrow().string()?.to_string();
+                    let separator = args[1].borrow().string()?.to_string();
+                    let parts: Vec<String> = if separator.is_empty() {
+                        string.chars().map(String::from).collect()
+                    } else {
+                        string.split(&separator).map(String::from).collect()
+                    };
+                    Data::List(parts.into_iter().map(|part| Rc::new(RefCell::new(Data::String(part)))).collect())
+                }
+                SystemFunction::Join => {
+                    let list = args[0].borrow().list()?;
+                    let separator = args[1].borrow().string()?.to_string();
+                    Data::String(join(&list, &separator, runtime)?)
+                }
+                SystemFunction::Replace => {
+                    let string = args[0].borrow();
+                    let (string, from, to) = (string.string()?, args[1].borrow(), args[2].borrow());
+                    let (from, to) = (from.string()?, to.string()?);
+
+                    // An empty pattern matches between every character and at both ends
+                    let matches = if from.is_empty() { string.chars().count() + 1 } else { string.matches(from).count() };
+                    runtime.check_size((string.len() - matches * from.len()).saturating_add(matches.saturating_mul(to.len())))?;
+
+                    Data::String(string.replace(from, to))
+                }
+                SystemFunction::Contains => Data::Boolean(args[0].borrow().string()?.contains(args[1].borrow().string()?)),
+                SystemFunction::StartsWith => Data::Boolean(args[0].borrow().string()?.starts_with(args[1].borrow().string()?)),
+                SystemFunction::EndsWith => Data::Boolean(args[0].borrow().string()?.ends_with(args[1].borrow().string()?)),
+                SystemFunction::Upper => Data::String(args[0].borrow().string()?.to_uppercase()),
+                SystemFunction::Lower => Data::String(args[0].borrow().string()?.to_lowercase()),
+                SystemFunction::CharAt => {
+                    let string = args[0].borrow().string()?.chars().collect::<Vec<_>>();
+                    let index = args[1].borrow().number()?;
+                    Data::String(
+                        list_index(&string, index)
+                            .ok_or(RuntimeError::IndexOutOfRange { index, length: string.len() })?
+                            .to_string(),
+                    )
+                }
+                SystemFunction::Format => Data::String(format(args[0].borrow().string()?, &args[1..], runtime)?),
+                SystemFunction::ToString => Data::String(display(&args[0].borrow(), runtime)?),
             }
-        ))
+        ));
+
+        runtime.allocate(data.borrow().size())?;
+        Ok(data)
     }
 
-    pub fn signature(&self) -> OptionalStatic<FunctionSignature> { // TODO: Optimise with lazy static
+    pub fn signature(&self) -> &'static FunctionSignature {
         match self {
-            SystemFunction::Stdin => OptionalStatic::Static(&STDIN),
-            SystemFunction::Number => OptionalStatic::Static(&NUMBER),
-            SystemFunction::Trim => OptionalStatic::Static(&TRIM),
-            SystemFunction::Not => OptionalStatic::Static(&NOT),
-            SystemFunction::And => OptionalStatic::Static(&AND),
-            SystemFunction::Or => OptionalStatic::Static(&OR),
-            SystemFunction::Xor => OptionalStatic::Static(&XOR),
-            SystemFunction::Add => OptionalStatic::Static(&ADD),
-            SystemFunction::Sub => OptionalStatic::Static(&SUB),
-            SystemFunction::Mul => OptionalStatic::Static(&MUL),
-            SystemFunction::Div => OptionalStatic::Static(&DIV),
-            SystemFunction::Mod => OptionalStatic::Static(&MOD),
-            SystemFunction::Println => OptionalStatic::Static(&PRINTLN),
-            SystemFunction::Break => OptionalStatic::Static(&BREAK),
-            SystemFunction::Continue => OptionalStatic::Static(&CONTINUE),
-            SystemFunction::Return => OptionalStatic::Static(&RETURN),
-            SystemFunction::Cmp => OptionalStatic::Static(&CMP),
-            SystemFunction::GreaterThan => OptionalStatic::Static(&GREATER_THAN),
-            SystemFunction::GreaterThanOrEqual => OptionalStatic::Static(&GREATER_THAN_OR_EQUAL),
-            SystemFunction::LessThan => OptionalStatic::Static(&LESS_THAN),
-            SystemFunction::LessThanOrEqual => OptionalStatic::Static(&LESS_THAN_OR_EQUAL),
-            SystemFunction::Push => OptionalStatic::Static(&PUSH),
-            SystemFunction::Pop => OptionalStatic::Static(&POP),
-            SystemFunction::Index => OptionalStatic::Static(&INDEX),
-            SystemFunction::Length => OptionalStatic::Static(&LENGTH),
-            SystemFunction::List => OptionalStatic::Static(&LIST),
+            SystemFunction::Stdin => &STDIN,
+            SystemFunction::Number => &NUMBER,
+            SystemFunction::Trim => &TRIM,
+            SystemFunction::Not => &NOT,
+            SystemFunction::And => &AND,
+            SystemFunction::Or => &OR,
+            SystemFunction::Xor => &XOR,
+            SystemFunction::Add => &ADD,
+            SystemFunction::Sub => &SUB,
+            SystemFunction::Mul => &MUL,
+            SystemFunction::Div => &DIV,
+            SystemFunction::Mod => &MOD,
+            SystemFunction::Println => &PRINTLN,
+            SystemFunction::Break => &BREAK,
+            SystemFunction::Continue => &CONTINUE,
+            SystemFunction::Return => &RETURN,
+            SystemFunction::ReturnUnit => &RETURN_UNIT,
+            SystemFunction::Cmp => &CMP,
+            SystemFunction::GreaterThan => &GREATER_THAN,
+            SystemFunction::GreaterThanOrEqual => &GREATER_THAN_OR_EQUAL,
+            SystemFunction::LessThan => &LESS_THAN,
+            SystemFunction::LessThanOrEqual => &LESS_THAN_OR_EQUAL,
+            SystemFunction::Push => &PUSH,
+            SystemFunction::Pop => &POP,
+            SystemFunction::Index => &INDEX,
+            SystemFunction::Length => &LENGTH,
+            SystemFunction::List => &LIST,
+            SystemFunction::Range => &RANGE,
+            SystemFunction::RangeStep => &RANGE_STEP,
+            SystemFunction::Call => &CALL,
+            SystemFunction::Map => &MAP,
+            SystemFunction::Filter => &FILTER,
+            SystemFunction::Fold => &FOLD,
+            SystemFunction::Reduce => &REDUCE,
+            SystemFunction::Sort => &SORT,
+            SystemFunction::SortBy => &SORT_BY,
+            SystemFunction::Any => &ANY,
+            SystemFunction::All => &ALL,
+            SystemFunction::Find => &FIND,
+            SystemFunction::Zip => &ZIP,
+            SystemFunction::Enumerate => &ENUMERATE,
+            SystemFunction::Reverse => &REVERSE,
+            SystemFunction::Slice => &SLICE,
+            SystemFunction::Concat => &CONCAT,
+            SystemFunction::NewMap => &NEW_MAP,
+            SystemFunction::Get => &GET,
+            SystemFunction::Set => &SET,
+            SystemFunction::Has => &HAS,
+            SystemFunction::Remove => &REMOVE,
+            SystemFunction::Keys => &KEYS,
+            SystemFunction::Values => &VALUES,
+            SystemFunction::Entries => &ENTRIES,
+            SystemFunction::StringConcat => &STRING_CONCAT,
+            SystemFunction::StringLength => &STRING_LENGTH,
+            SystemFunction::Substring => &SUBSTRING,
+            SystemFunction::StringSlice => &STRING_SLICE,
+            SystemFunction::Split => &SPLIT,
+            SystemFunction::Join => &JOIN,
+            SystemFunction::Replace => &REPLACE,
+            SystemFunction::Contains => &CONTAINS,
+            SystemFunction::StartsWith => &STARTS_WITH,
+            SystemFunction::EndsWith => &ENDS_WITH,
+            SystemFunction::Upper => &UPPER,
+            SystemFunction::Lower => &LOWER,
+            SystemFunction::CharAt => &CHAR_AT,
+            SystemFunction::Format => &FORMAT,
+            SystemFunction::ToString => &TO_STRING,
         }
     }
 }
@@ -141,20 +482,217 @@ static PRINTLN: Lazy<FunctionSignature> = Lazy::new(|| signature!("println".into
 static BREAK: Lazy<FunctionSignature> = Lazy::new(|| signature!("break".into(), return_control(), false));
 static CONTINUE: Lazy<FunctionSignature> = Lazy::new(|| signature!("continue".into(), return_control(), false));
 static RETURN: Lazy<FunctionSignature> = Lazy::new(|| signature!("return".into(), return_control(), false, arg_any()));
+static RETURN_UNIT: Lazy<FunctionSignature> = Lazy::new(|| signature!("return".into(), return_control(), false));
 static CMP: Lazy<FunctionSignature> = Lazy::new(|| signature!("==".into(), return_boolean(), false, arg_any(), arg_any()));
 static GREATER_THAN: Lazy<FunctionSignature> = Lazy::new(|| signature!(">".into(), return_boolean(), false, arg_number(), arg_number()));
 static GREATER_THAN_OR_EQUAL: Lazy<FunctionSignature> = Lazy::new(|| signature!(">=".into(), return_boolean(), false, arg_number(), arg_number()));
 static LESS_THAN: Lazy<FunctionSignature> = Lazy::new(|| signature!("<".into(), return_boolean(), false, arg_number(), arg_number()));
 static LESS_THAN_OR_EQUAL: Lazy<FunctionSignature> = Lazy::new(|| signature!("<=".into(), return_boolean(), false, arg_number(), arg_number()));
 static PUSH: Lazy<FunctionSignature> = Lazy::new(|| signature!("push".into(), return_unit(), true, arg_list(), arg_any()));
-static POP: Lazy<FunctionSignature> = Lazy::new(|| signature!("pop".into(), return_unit(), true, arg_list()));
-static INDEX: Lazy<FunctionSignature> = Lazy::new(|| signature!("index".into(), return_any(), true, arg_list(), arg_number()));
-static LENGTH: Lazy<FunctionSignature> = Lazy::new(|| signature!("length".into(), return_number(), true, arg_list()));
+static POP: Lazy<FunctionSignature> = Lazy::new(|| signature!("pop".into(), return_any(), false, arg_list()));
+static INDEX: Lazy<FunctionSignature> = Lazy::new(|| signature!("index".into(), return_any(), false, arg_list(), arg_number()));
+static LENGTH: Lazy<FunctionSignature> = Lazy::new(|| signature!("length".into(), return_number(), false, arg_list()));
 static LIST: Lazy<FunctionSignature> = Lazy::new(|| signature!("list".into(), return_list(), true, arg_any()));
+static RANGE: Lazy<FunctionSignature> = Lazy::new(|| signature!("range".into(), return_list(), false, arg_number(), arg_number()));
+static RANGE_STEP: Lazy<FunctionSignature> = Lazy::new(|| signature!("range".into(), return_list(), false, arg_number(), arg_number(), arg_number()));
+static CALL: Lazy<FunctionSignature> = Lazy::new(|| signature!("call".into(), return_any(), true, arg_function(), arg_any()));
+static MAP: Lazy<FunctionSignature> = Lazy::new(|| signature!("map".into(), return_list(), false, arg_list(), arg_function()));
+static FILTER: Lazy<FunctionSignature> = Lazy::new(|| signature!("filter".into(), return_list(), false, arg_list(), arg_function()));
+static FOLD: Lazy<FunctionSignature> = Lazy::new(|| signature!("fold".into(), return_any(), false, arg_list(), arg_any(), arg_function()));
+static REDUCE: Lazy<FunctionSignature> = Lazy::new(|| signature!("reduce".into(), return_any(), false, arg_list(), arg_function()));
+static SORT: Lazy<FunctionSignature> = Lazy::new(|| signature!("sort".into(), return_list(), false, arg_list()));
+static SORT_BY: Lazy<FunctionSignature> = Lazy::new(|| signature!("sort_by".into(), return_list(), false, arg_list(), arg_function()));
+static ANY: Lazy<FunctionSignature> = Lazy::new(|| signature!("any".into(), return_boolean(), false, arg_list(), arg_function()));
+static ALL: Lazy<FunctionSignature> = Lazy::new(|| signature!("all".into(), return_boolean(), false, arg_list(), arg_function()));
+static FIND: Lazy<FunctionSignature> = Lazy::new(|| signature!("find".into(), return_any(), false, arg_list(), arg_function()));
+static ZIP: Lazy<FunctionSignature> = Lazy::new(|| signature!("zip".into(), return_list(), false, arg_list(), arg_list()));
+static ENUMERATE: Lazy<FunctionSignature> = Lazy::new(|| signature!("enumerate".into(), return_list(), false, arg_list()));
+static REVERSE: Lazy<FunctionSignature> = Lazy::new(|| signature!("reverse".into(), return_list(), false, arg_list()));
+static SLICE: Lazy<FunctionSignature> = Lazy::new(|| signature!("slice".into(), return_list(), false, arg_list(), arg_number(), arg_number()));
+static CONCAT: Lazy<FunctionSignature> = Lazy::new(|| signature!("concat".into(), return_list(), true, arg_list()));
+static NEW_MAP: Lazy<FunctionSignature> = Lazy::new(|| signature!("map".into(), return_map(), true, arg_any()));
+static GET: Lazy<FunctionSignature> = Lazy::new(|| signature!("get".into(), return_any(), false, arg_map(), arg_string()));
+static SET: Lazy<FunctionSignature> = Lazy::new(|| signature!("set".into(), return_unit(), false, arg_map(), arg_string(), arg_any()));
+static HAS: Lazy<FunctionSignature> = Lazy::new(|| signature!("has".into(), return_boolean(), false, arg_map(), arg_string()));
+static REMOVE: Lazy<FunctionSignature> = Lazy::new(|| signature!("remove".into(), return_any(), false, arg_map(), arg_string()));
+static KEYS: Lazy<FunctionSignature> = Lazy::new(|| signature!("keys".into(), return_list(), false, arg_map()));
+static VALUES: Lazy<FunctionSignature> = Lazy::new(|| signature!("values".into(), return_list(), false, arg_map()));
+static ENTRIES: Lazy<FunctionSignature> = Lazy::new(|| signature!("entries".into(), return_list(), false, arg_map()));
+static STRING_CONCAT: Lazy<FunctionSignature> = Lazy::new(|| signature!("concat".into(), return_string(), true, arg_string()));
+static STRING_LENGTH: Lazy<FunctionSignature> = Lazy::new(|| signature!("length".into(), return_number(), false, arg_string()));
+static SUBSTRING: Lazy<FunctionSignature> = Lazy::new(|| signature!("substring".into(), return_string(), false, arg_string(), arg_number(), arg_number()));
+static STRING_SLICE: Lazy<FunctionSignature> = Lazy::new(|| signature!("slice".into(), return_string(), false, arg_string(), arg_number(), arg_number()));
+static SPLIT: Lazy<FunctionSignature> = Lazy::new(|| signature!("split".into(), return_list(), false, arg_string(), arg_string()));
+static JOIN: Lazy<FunctionSignature> = Lazy::new(|| signature!("join".into(), return_string(), false, arg_list(), arg_string()));
+static REPLACE: Lazy<FunctionSignature> = Lazy::new(|| signature!("replace".into(), return_string(), false, arg_string(), arg_string(), arg_string()));
+static CONTAINS: Lazy<FunctionSignature> = Lazy::new(|| signature!("contains".into(), return_boolean(), false, arg_string(), arg_string()));
+static STARTS_WITH: Lazy<FunctionSignature> = Lazy::new(|| signature!("starts_with".into(), return_boolean(), false, arg_string(), arg_string()));
+static ENDS_WITH: Lazy<FunctionSignature> = Lazy::new(|| signature!("ends_with".into(), return_boolean(), false, arg_string(), arg_string()));
+static UPPER: Lazy<FunctionSignature> = Lazy::new(|| signature!("upper".into(), return_string(), false, arg_string()));
+static LOWER: Lazy<FunctionSignature> = Lazy::new(|| signature!("lower".into(), return_string(), false, arg_string()));
+static CHAR_AT: Lazy<FunctionSignature> = Lazy::new(|| signature!("char_at".into(), return_string(), false, arg_string(), arg_number()));
+static FORMAT: Lazy<FunctionSignature> = Lazy::new(|| signature!("format".into(), return_string(), true, arg_string(), arg_any()));
+static TO_STRING: Lazy<FunctionSignature> = Lazy::new(|| signature!("to_string".into(), return_string(), false, arg_any()));
 
-fn operator_impl(operation: impl FnMut(f64, &Rc<RefCell<Data>>) -> f64, args: &[Rc<RefCell<Data>>]) -> Data {
-    let mut iter = args.iter();
+fn operator_impl(operation: impl FnMut(f64, f64) -> f64, args: &[Rc<RefCell<Data>>]) -> Result<Data, RuntimeError> {
+    let numbers = args.iter().map(|arg| arg.borrow().number()).collect::<Result<Vec<_>, _>>()?;
+    let mut iter = numbers.into_iter();
     iter.next()
-        .map(|start| Data::Number(iter.fold(start.borrow().number(), operation)))
-        .expect("Expected at least one argument")
+        .map(|start| Data::Number(iter.fold(start, operation)))
+        .ok_or_else(|| RuntimeError::InvalidArgument("expected at least one argument".into()))
+}
+
+fn boolean_args(args: &[Rc<RefCell<Data>>]) -> Result<Vec<bool>, RuntimeError> {
+    args.iter().map(|arg| arg.borrow().boolean()).collect()
+}
+
+/// The `start` and `end` index arguments of a slice of something `length` long
+fn range(args: &[Rc<RefCell<Data>>], length: usize) -> Result<(usize, usize), RuntimeError> {
+    let bound = |arg: &Rc<RefCell<Data>>| {
+        let index = arg.borrow().number()?;
+        (index >= 0. && index.fract() == 0. && index as usize <= length)
+            .then_some(index as usize)
+            .ok_or(RuntimeError::IndexOutOfRange { index, length })
+    };
+
+    let (start, end) = (bound(&args[0])?, bound(&args[1])?);
+    if start > end {
+        return Err(RuntimeError::InvalidArgument(format!(
+            "slice start {start} is after its end {end}"
+        )));
+    }
+
+    Ok((start, end))
+}
+
+/// Replace each `{}` in `template` with the next argument. `{{` and `}}` stand for literal braces
+/// Display `data`, failing as soon as the string grows past what the program may still create
+/// so a list holding the same large value many times is never displayed in full
+fn display(data: &Data, runtime: &Runtime) -> Result<String, RuntimeError> {
+    struct Bounded<'a> {
+        string: String,
+        runtime: &'a Runtime,
+        error: Option<RuntimeError>,
+    }
+
+    impl fmt::Write for Bounded<'_> {
+        fn write_str(&mut self, s: &str) -> fmt::Result {
+            if let Err(error) = self.runtime.check_size(self.string.len() + s.len()) {
+                self.error = Some(error);
+                return Err(fmt::Error);
+            }
+
+            self.string.push_str(s);
+            Ok(())
+        }
+    }
+
+    let mut bounded = Bounded { string: String::new(), runtime, error: None };
+    let _ = write!(bounded, "{data}");
+    bounded.error.map_or(Ok(bounded.string), Err)
+}
+
+/// Display `elements` with `separator` between them, checking the size of the string before each is added
+fn join(elements: &[Rc<RefCell<Data>>], separator: &str, runtime: &Runtime) -> Result<String, RuntimeError> {
+    let mut joined = String::new();
+
+    for (i, element) in elements.iter().enumerate() {
+        let separator = if i == 0 { "" } else { separator };
+        let element = display(&element.borrow(), runtime)?;
+        runtime.check_size(joined.len() + separator.len() + element.len())?;
+
+        joined.push_str(separator);
+        joined.push_str(&element);
+    }
+
+    Ok(joined)
+}
+
+fn format(template: &str, args: &[Rc<RefCell<Data>>], runtime: &Runtime) -> Result<String, RuntimeError> {
+    let mut result = String::new();
+    let mut args = args.iter();
+    let mut chars = template.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match (c, chars.peek()) {
+            ('{', Some('{')) | ('}', Some('}')) => {
+                chars.next();
+                result.push(c);
+            }
+            ('{', Some('}')) => {
+                chars.next();
+                let arg = args
+                    .next()
+                    .ok_or_else(|| RuntimeError::InvalidArgument("format has more `{}` than arguments".into()))?;
+                let arg = display(&arg.borrow(), runtime)?;
+                runtime.check_size(result.len() + arg.len())?;
+                result.push_str(&arg);
+            }
+            ('{' | '}', _) => {
+                return Err(RuntimeError::InvalidArgument(format!(
+                    "unmatched `{c}` in format string, use `{c}{c}` for a literal one"
+                )))
+            }
+            _ => result.push(c),
+        }
+    }
+
+    if args.next().is_some() {
+        return Err(RuntimeError::InvalidArgument("format has more arguments than `{}`".into()));
+    }
+
+    Ok(result)
+}
+
+/// Call `function` on `element`, which has to return a boolean
+fn predicate(function: &FunctionValue, element: &Rc<RefCell<Data>>, runtime: &mut Runtime) -> Result<bool, RuntimeError> {
+    function.call(slice::from_ref(element), runtime)?.borrow().boolean()
+}
+
+/// The natural order of numbers and strings
+fn less_than(a: &Data, b: &Data) -> Result<bool, RuntimeError> {
+    match (a, b) {
+        (Data::Number(a), Data::Number(b)) => Ok(a < b),
+        (Data::String(a), Data::String(b)) => Ok(a < b),
+        (Data::Number(_) | Data::String(_), _) => Err(b.mismatch(a.type_name())),
+        _ => Err(a.mismatch("number or string")),
+    }
+}
+
+/// A stable sort that stops at the first error raised by `less`.
+/// Comparisons written in Func# may be inconsistent, so this does not rely on them being a total order
+fn merge_sort<F>(mut list: Vec<Rc<RefCell<Data>>>, less: &mut F) -> Result<Vec<Rc<RefCell<Data>>>, RuntimeError>
+where
+    F: FnMut(&Rc<RefCell<Data>>, &Rc<RefCell<Data>>) -> Result<bool, RuntimeError>,
+{
+    if list.len() <= 1 {
+        return Ok(list);
+    }
+
+    let right = merge_sort(list.split_off(list.len() / 2), less)?;
+    let left = merge_sort(list, less)?;
+
+    let mut merged = Vec::with_capacity(left.len() + right.len());
+    let (mut left, mut right) = (left.into_iter().peekable(), right.into_iter().peekable());
+    while let (Some(a), Some(b)) = (left.peek(), right.peek()) {
+        // Taking from the left unless the right is strictly smaller keeps equal elements in order
+        if less(b, a)? {
+            merged.push(right.next().unwrap());
+        } else {
+            merged.push(left.next().unwrap());
+        }
+    }
+    merged.extend(left);
+    merged.extend(right);
+
+    Ok(merged)
+}
+
+/// Get the element at `index`, rejecting negative and fractional indices
+fn list_index<T>(list: &[T], index: f64) -> Option<&T> {
+    if index < 0. || index.fract() != 0. {
+        return None;
+    }
+
+    list.get(index as usize)
 }
\ No newline at end of file
@@ -0,0 +1,305 @@
+use std::{
+    cell::RefCell,
+    mem,
+    rc::Rc,
+};
+
+use super::{
+    bytecode::{Callee, Chunk, Instruction, Operator, Program},
+    runtime::Runtime,
+    scope::{FunctionSignature, ReturnType, VariableScope},
+    Data, FunctionSource, RuntimeError,
+};
+
+/// Where to resume once a function returns
+struct Frame<'p> {
+    chunk: &'p Chunk,
+    ip: usize,
+    /// Index of the frame's first local slot
+    base: usize,
+    /// The function being executed, or `None` for the top level of the program
+    signature: Option<&'p FunctionSignature>,
+}
+
+/// The state of a running [`Program`]
+struct Machine<'p> {
+    program: &'p Program,
+    frame: Frame<'p>,
+    frames: Vec<Frame<'p>>,
+    stack: Vec<Rc<RefCell<Data>>>,
+    locals: Vec<Option<Rc<RefCell<Data>>>>,
+    globals: Vec<Option<Rc<RefCell<Data>>>>,
+    /// Passed to system functions, which expect the state of the tree-walking interpreter
+    runtime: Runtime,
+    variable_scope: Rc<RefCell<VariableScope>>,
+}
+
+impl Program {
+    /// Run the program, returning the value of its last top level invocation
+    pub fn run(&self) -> Result<Rc<RefCell<Data>>, RuntimeError> {
+        self.run_with(Runtime::default())
+    }
+
+    /// Run the program with the limits and state of `runtime`
+    pub fn run_with(&self, mut runtime: Runtime) -> Result<Rc<RefCell<Data>>, RuntimeError> {
+        runtime.start();
+        let mut machine = Machine {
+            program: self,
+            frame: Frame {
+                chunk: &self.main,
+                ip: 0,
+                base: 0,
+                signature: None,
+            },
+            frames: Vec::new(),
+            stack: Vec::new(),
+            locals: vec![None; self.main.locals.len()],
+            globals: self
+                .globals
+                .iter()
+                .map(|global| global.initial.clone().map(|data| Rc::new(RefCell::new(data))))
+                .collect(),
+            runtime,
+            variable_scope: Rc::default(),
+        };
+
+        loop {
+            let ip = machine.frame.ip;
+            let chunk = machine.frame.chunk;
+            machine.frame.ip += 1;
+
+            match machine.step(chunk.code[ip]) {
+                Ok(Some(result)) => return Ok(result),
+                Ok(None) => {}
+                Err(e) => return Err(e.located(chunk.spans[ip])),
+            }
+        }
+    }
+}
+
+impl<'p> Machine<'p> {
+    /// Execute one instruction, returning the result of the program once it finishes
+    fn step(&mut self, instruction: Instruction) -> Result<Option<Rc<RefCell<Data>>>, RuntimeError> {
+        // Invocations and loop iterations are the steps counted, as when interpreting
+        let counted = match instruction {
+            Instruction::Binary(_)
+            | Instruction::CallBuiltin(..)
+            | Instruction::Call(..)
+            | Instruction::TailCall(..)
+            | Instruction::CallDynamic(..) => true,
+            Instruction::Jump(target) => target < self.frame.ip,
+            _ => false,
+        };
+        if counted {
+            self.runtime.step()?;
+        }
+
+        match instruction {
+            Instruction::Constant(index) => self.push(self.program.constants[index].clone()),
+            Instruction::Unit => self.push(Data::Unit),
+            Instruction::LoadLocal(slot) => {
+                let value = self.locals[self.frame.base + slot]
+                    .clone()
+                    .ok_or_else(|| RuntimeError::VariableNotFound(self.frame.chunk.locals[slot].clone()))?;
+                self.stack.push(value);
+            }
+            Instruction::LoadGlobal(slot) => {
+                let value = self.globals[slot].clone().ok_or_else(|| {
+                    RuntimeError::VariableNotFound(self.program.globals[slot].name.clone())
+                })?;
+                self.stack.push(value);
+            }
+            Instruction::BindLocal(slot) => self.locals[self.frame.base + slot] = self.stack.pop(),
+            Instruction::BindGlobal(slot) => self.globals[slot] = self.stack.pop(),
+            Instruction::AssignLocal(slot) => {
+                let data = self.pop().borrow().clone();
+                *self.locals[self.frame.base + slot]
+                    .as_ref()
+                    .ok_or_else(|| RuntimeError::VariableNotFound(self.frame.chunk.locals[slot].clone()))?
+                    .borrow_mut() = data;
+            }
+            Instruction::AssignGlobal(slot) => {
+                let data = self.pop().borrow().clone();
+                *self.globals[slot]
+                    .as_ref()
+                    .ok_or_else(|| {
+                        RuntimeError::VariableNotFound(self.program.globals[slot].name.clone())
+                    })?
+                    .borrow_mut() = data;
+            }
+            Instruction::Pop => {
+                self.pop();
+            }
+            Instruction::Jump(target) => self.frame.ip = target,
+            Instruction::JumpIfFalse(target) => {
+                if !self.pop().borrow().boolean()? {
+                    self.frame.ip = target;
+                }
+            }
+            Instruction::Next(slot, target) => {
+                let local = |slot: usize| {
+                    self.locals[self.frame.base + slot]
+                        .clone()
+                        .ok_or_else(|| RuntimeError::VariableNotFound(self.frame.chunk.locals[slot].clone()))
+                };
+                let (list, index) = (local(slot)?, local(slot + 1)?);
+
+                let position = index.borrow().number()? as usize;
+                let element = list.borrow().element(position)?;
+                match element {
+                    Some(element) => {
+                        *index.borrow_mut() = Data::Number((position + 1) as f64);
+                        self.push(element);
+                    }
+                    None => self.frame.ip = target,
+                }
+            }
+            Instruction::Binary(operator) => {
+                let right = self.pop();
+                let left = self.pop();
+                let result = operator.apply(&left.borrow(), &right.borrow())?;
+                self.runtime.allocate(result.size())?;
+
+                // Reuse the allocation of temporaries
+                if Rc::strong_count(&left) == 1 {
+                    *left.borrow_mut() = result;
+                    self.stack.push(left);
+                } else {
+                    self.push(result);
+                }
+            }
+            Instruction::CallBuiltin(index, count) => {
+                let args = self.stack.split_off(self.stack.len() - count);
+                let result = self.call_builtin(index, &args)?;
+                self.stack.push(result);
+            }
+            Instruction::Call(index, count) => self.enter(index, count, false)?,
+            Instruction::TailCall(index, count) => self.enter(index, count, true)?,
+            Instruction::CallDynamic(set, count) => {
+                let args = &self.stack[self.stack.len() - count..];
+                let callees = &self.program.overloads[set];
+                let signature = |callee: &Callee| match *callee {
+                    Callee::Builtin(index) => self.program.builtins[index].signature(),
+                    Callee::Defined(index) => &self.program.functions[index].signature,
+                };
+
+                match callees.iter().find(|callee| signature(callee).accepts_data(args)) {
+                    Some(Callee::Builtin(index)) => {
+                        let args = self.stack.split_off(self.stack.len() - count);
+                        let result = self.call_builtin(*index, &args)?;
+                        self.stack.push(result);
+                    }
+                    Some(Callee::Defined(index)) => self.enter(*index, count, false)?,
+                    None => {
+                        return Err(RuntimeError::FunctionNotFound {
+                            name: signature(&callees[0]).name.clone(),
+                            args: types(args),
+                        })
+                    }
+                }
+            }
+            Instruction::Return => {
+                let result = self.pop();
+                if let Some(signature) = self.frame.signature {
+                    signature.check_return(&result.borrow())?;
+                }
+                self.locals.truncate(self.frame.base);
+
+                match self.frames.pop() {
+                    Some(frame) => {
+                        self.frame = frame;
+                        self.stack.push(result);
+                    }
+                    None => return Ok(Some(result)),
+                }
+            }
+        }
+
+        Ok(None)
+    }
+
+    fn push(&mut self, data: Data) {
+        self.stack.push(Rc::new(RefCell::new(data)));
+    }
+
+    fn pop(&mut self) -> Rc<RefCell<Data>> {
+        self.stack.pop().expect("Stack underflow")
+    }
+
+    fn call_builtin(
+        &mut self,
+        index: usize,
+        args: &[Rc<RefCell<Data>>],
+    ) -> Result<Rc<RefCell<Data>>, RuntimeError> {
+        match &*self.program.builtins[index] {
+            FunctionSource::System(function) => {
+                function.execute(args, &mut self.runtime, self.variable_scope.clone())
+            }
+            FunctionSource::Native(function) => function.execute(args),
+            _ => unreachable!("Only system and native functions are compiled as builtins"),
+        }
+    }
+
+    /// Start executing a compiled function with the top `count` values as its arguments.
+    /// A tail call replaces the frame of the current function instead of returning to it
+    fn enter(&mut self, index: usize, count: usize, tail: bool) -> Result<(), RuntimeError> {
+        let function = &self.program.functions[index];
+        let args = self.stack.split_off(self.stack.len() - count);
+
+        if !function.signature.accepts_data(&args) {
+            return Err(RuntimeError::FunctionNotFound {
+                name: function.signature.name.clone(),
+                args: types(&args),
+            });
+        }
+
+        let max_depth = self.runtime.limits().max_depth;
+        if !tail && self.frames.len() >= max_depth {
+            return Err(RuntimeError::StackOverflow(max_depth));
+        }
+
+        if tail {
+            self.locals.truncate(self.frame.base);
+        }
+        let base = self.locals.len();
+        self.locals.extend(args.into_iter().map(Some));
+        self.locals.resize(base + function.chunk.locals.len(), None);
+
+        let frame = Frame {
+            chunk: &function.chunk,
+            ip: 0,
+            base,
+            signature: Some(&function.signature),
+        };
+        if tail {
+            self.frame = frame;
+        } else {
+            self.frames.push(mem::replace(&mut self.frame, frame));
+        }
+
+        Ok(())
+    }
+}
+
+impl Operator {
+    fn apply(self, left: &Data, right: &Data) -> Result<Data, RuntimeError> {
+        Ok(match self {
+            Operator::Add => Data::Number(left.number()? + right.number()?),
+            Operator::Sub => Data::Number(left.number()? - right.number()?),
+            Operator::Mul => Data::Number(left.number()? * right.number()?),
+            Operator::Div => Data::Number(left.number()? / right.number()?),
+            Operator::Mod => Data::Number(left.number()? % right.number()?),
+            Operator::Equal => Data::Boolean(left == right),
+            Operator::GreaterThan => Data::Boolean(left.number()? > right.number()?),
+            Operator::GreaterThanOrEqual => Data::Boolean(left.number()? >= right.number()?),
+            Operator::LessThan => Data::Boolean(left.number()? < right.number()?),
+            Operator::LessThanOrEqual => Data::Boolean(left.number()? <= right.number()?),
+        })
+    }
+}
+
+fn types(args: &[Rc<RefCell<Data>>]) -> Vec<ReturnType> {
+    args.iter()
+        .map(|arg| ReturnType::Data(mem::discriminant(&*arg.borrow())))
+        .collect()
+}
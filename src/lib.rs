@@ -4,4 +4,3 @@ extern crate pest_derive;
 
 pub mod interpreter;
 pub mod parser;
-pub mod util;
\ No newline at end of file
@@ -1,33 +1,366 @@
-use std::{fs, time::Instant, mem};
+use std::{
+    env, fs,
+    io::{self, Read, Write},
+    iter,
+    process::ExitCode,
+    thread,
+    time::{Duration, Instant},
+};
 
-use func_sharp::{parser::{FuncParser, self}, interpreter::{Invocation, self}};
-use pest::Parser;
+use func_sharp::{
+    interpreter::{Data, Interpreter, IoPolicy, Limits, Runtime},
+    parser::{self, Parenthesis},
+};
 
-fn main() {
-    let input = "prime.funcs";
+const USAGE: &str = "\
+Usage: func_sharp <command> [options] [file]
+
+Commands:
+    run [file]      Parse and execute a program
+    check [file]    Parse and type check a program without executing it
+    repl            Evaluate invocations interactively
+    help            Print this message
+
+Options:
+    --time          Print read, parse and execution timings to stderr
+    --vm            Compile the program to bytecode and run it on the stack machine
+    --unchecked     Run the program without type checking it first
+    --max-depth <n> Raise a stack overflow error once calls nest deeper than n (default 1000)
+    --max-steps <n> Stop the program once it has evaluated n invocations and loop iterations
+    --max-size <n>  Stop the program once it has created n list and map elements and string bytes
+    --timeout <ms>  Stop the program once it has run for ms milliseconds
+    --module-root <dir>
+                    Only let `import` read files inside dir
+
+The program is read from stdin when no file (or `-`) is given.
+
+Exit codes:
+    0   Success
+    1   The program raised a runtime error
+    2   Invalid command line usage
+    3   The program could not be read
+    4   The program failed to parse
+    5   The program could not be compiled to bytecode
+    6   The program failed to type check";
+
+/// The exit codes reported back to the calling process
+#[derive(Debug, Clone, Copy)]
+enum Status {
+    Success = 0,
+    Runtime = 1,
+    Usage = 2,
+    Io = 3,
+    Parse = 4,
+    Compile = 5,
+    Type = 6,
+}
+
+impl From<Status> for ExitCode {
+    fn from(value: Status) -> Self {
+        ExitCode::from(value as u8)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Command {
+    Run,
+    Check,
+    Repl,
+}
+
+/// The parsed command line
+struct Options {
+    command: Command,
+    input: Option<String>,
+    time: bool,
+    vm: bool,
+    unchecked: bool,
+    limits: Limits,
+    module_root: Option<String>,
+}
+
+fn main() -> ExitCode {
+    let options = match parse_args(env::args().skip(1)) {
+        Ok(Some(options)) => options,
+        Ok(None) => {
+            println!("{USAGE}");
+            return Status::Success.into();
+        }
+        Err(message) => {
+            eprintln!("error: {message}\n\n{USAGE}");
+            return Status::Usage.into();
+        }
+    };
+
+    // Calls nest on the native stack, so it has to be large enough for the deepest allowed
+    let status = thread::Builder::new()
+        .stack_size(options.limits.stack_size())
+        .spawn(move || match options.command {
+            Command::Repl => repl(&options),
+            _ => run(options),
+        })
+        .and_then(|handle| handle.join().map_err(|_| io::Error::other("the interpreter panicked")));
+
+    match status {
+        Ok(status) => status.into(),
+        Err(e) => {
+            eprintln!("error: {e}");
+            Status::Runtime.into()
+        }
+    }
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Option<Options>, String> {
+    let command = match args.next().as_deref() {
+        Some("run") => Command::Run,
+        Some("check") => Command::Check,
+        Some("repl") => Command::Repl,
+        Some("help" | "-h" | "--help") => return Ok(None),
+        Some(other) => return Err(format!("unknown command `{other}`")),
+        None => return Err("no command given".into()),
+    };
+
+    let mut options = Options {
+        command,
+        input: None,
+        time: false,
+        vm: false,
+        unchecked: false,
+        limits: Limits::default(),
+        module_root: None,
+    };
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--time" => options.time = true,
+            "--vm" => options.vm = true,
+            "--unchecked" => options.unchecked = true,
+            "--max-depth" => {
+                let max_depth = number(args.next(), "--max-depth takes a number of calls")?;
+                options.limits = options.limits.with_max_depth(max_depth);
+            }
+            "--max-steps" => options.limits.max_steps = Some(number(args.next(), "--max-steps takes a number of steps")?),
+            "--max-size" => options.limits.max_size = Some(number(args.next(), "--max-size takes a number of elements")?),
+            "--timeout" => {
+                let millis = number(args.next(), "--timeout takes a number of milliseconds")?;
+                options.limits.timeout = Some(Duration::from_millis(millis as u64));
+            }
+            "--module-root" => options.module_root = Some(args.next().ok_or("--module-root takes a directory")?),
+            "-h" | "--help" => return Ok(None),
+            "-" => options.input = None,
+            flag if flag.starts_with('-') => return Err(format!("unknown option `{flag}`")),
+            _ if options.command == Command::Repl => return Err("repl does not take an input file".into()),
+            _ if options.input.is_some() => return Err("only one input file may be given".into()),
+            path => options.input = Some(path.to_string()),
+        }
+    }
+
+    Ok(Some(options))
+}
+
+/// The side effects programs run from the command line may have
+fn io_policy(options: &Options) -> IoPolicy {
+    match &options.module_root {
+        Some(root) => IoPolicy::default().with_imports_within(root),
+        None => IoPolicy::default(),
+    }
+}
+
+/// Parse the value of an option, failing with `message` if it is missing or not a number
+fn number(value: Option<String>, message: &str) -> Result<usize, String> {
+    value.and_then(|value| value.parse().ok()).ok_or_else(|| message.to_string())
+}
+
+fn run(options: Options) -> Status {
+    let path = options.input.as_deref().unwrap_or("<stdin>");
 
     let start_read_time = Instant::now();
-    let source = fs::read_to_string(input).unwrap();
-    println!("Read time: {}ms", start_read_time.elapsed().as_millis());
+    let source = match read_source(options.input.as_deref()) {
+        Ok(source) => source,
+        Err(e) => {
+            eprintln!("error: could not read {path}: {e}");
+            return Status::Io;
+        }
+    };
+    if options.time {
+        eprintln!("Read time: {}ms", start_read_time.elapsed().as_millis());
+    }
 
     let start_parse_time = Instant::now();
-    let program = match FuncParser::parse(parser::Rule::program, source.as_str()) {
-        Ok(mut parsed) => parsed.next().unwrap(),
+    let program = match parser::parse_program(&source) {
+        Ok(program) => program,
         Err(e) => {
-            println!("{}", e.with_path(input));
-            return;
+            eprintln!("{}", e.with_path(path));
+            return Status::Parse;
+        }
+    };
+    if options.time {
+        eprintln!("Parse time: {}ms", start_parse_time.elapsed().as_millis());
+    }
+
+    let mut interpreter = Interpreter::new();
+    interpreter.set_limits(options.limits.clone());
+    interpreter.set_io_policy(io_policy(&options));
+    if let Some(input) = options.input.as_deref() {
+        interpreter.set_path(input);
+    }
+
+    if !options.unchecked || options.command == Command::Check {
+        let start_check_time = Instant::now();
+        if let Err(errors) = interpreter.check(&program) {
+            for e in errors.iter() {
+                eprintln!("{}", e.render(&source, path));
+            }
+            eprintln!("error: found {} type error(s)", errors.len());
+            return Status::Type;
+        }
+        if options.time {
+            eprintln!("Check time: {}ms", start_check_time.elapsed().as_millis());
         }
-    }.into_inner().filter_map(|pair| match pair.as_rule() {
-        parser::Rule::invocation => Some(Invocation::from(pair)),
-        parser::Rule::EOI => None,
-        _ => unreachable!()
-    }).collect::<Vec<_>>();
-    println!("Parse time: {}ms", start_parse_time.elapsed().as_millis());
+    }
+
+    if options.command == Command::Check {
+        return Status::Success;
+    }
+
+    let compiled = if options.vm {
+        let start_compile_time = Instant::now();
+        let compiled = match interpreter.compile(&program) {
+            Ok(compiled) => compiled,
+            Err(e) => {
+                eprintln!("{}", e.render(&source, path));
+                return Status::Compile;
+            }
+        };
+        if options.time {
+            eprintln!("Compile time: {}ms", start_compile_time.elapsed().as_millis());
+        }
+        Some(compiled)
+    } else {
+        None
+    };
 
     let start_execution_time = Instant::now();
-    interpreter::execute(program);
-    println!(
-        "Execution time: {}ms",
-        start_execution_time.elapsed().as_millis()
-    );
+    let result = match compiled {
+        Some(compiled) => {
+            let mut runtime = Runtime::default();
+            runtime.set_io_policy(io_policy(&options));
+            runtime.set_limits(options.limits);
+            compiled.run_with(runtime).map(drop)
+        }
+        None => interpreter.evaluate(&program).map(drop),
+    };
+    if options.time {
+        eprintln!(
+            "Execution time: {}ms",
+            start_execution_time.elapsed().as_millis()
+        );
+    }
+
+    match result {
+        Ok(()) => Status::Success,
+        Err(e) => {
+            eprintln!("{}", e.render(&source, path));
+            Status::Runtime
+        }
+    }
+}
+
+fn read_source(input: Option<&str>) -> io::Result<String> {
+    match input {
+        Some(path) => fs::read_to_string(path),
+        None => {
+            let mut source = String::new();
+            io::stdin().read_to_string(&mut source)?;
+            Ok(source)
+        }
+    }
+}
+
+fn repl(options: &Options) -> Status {
+    let mut interpreter = Interpreter::new();
+    interpreter.set_limits(options.limits.clone());
+    interpreter.set_io_policy(io_policy(options));
+    // Stdin is only locked while a line is read, so that programs calling `stdin` can read it too
+    let mut lines = iter::from_fn(|| {
+        let mut line = String::new();
+        match io::stdin().read_line(&mut line) {
+            Ok(0) => None,
+            Ok(_) => Some(Ok(line.trim_end_matches(['\n', '\r']).to_string())),
+            Err(e) => Some(Err(e)),
+        }
+    });
+
+    loop {
+        let entry = match read_entry(&mut lines) {
+            Ok(Some(entry)) => entry,
+            Ok(None) => return Status::Success,
+            Err(e) => {
+                eprintln!("error: could not read input: {e}");
+                return Status::Io;
+            }
+        };
+
+        let entry = entry.trim();
+        if parser::is_blank(entry) {
+            continue;
+        }
+
+        let invocation = match parser::parse_invocation(entry) {
+            Ok(invocation) => invocation,
+            Err(e) => {
+                eprintln!("{}", e.with_path("<repl>"));
+                continue;
+            }
+        };
+
+        match interpreter.evaluate(&[invocation]) {
+            Ok(result) => {
+                if *result.borrow() != Data::Unit {
+                    println!("{}", result.borrow());
+                }
+            }
+            Err(e) => eprintln!("{}", e.render(entry, "<repl>")),
+        }
+    }
+}
+
+/// Read lines until every opened parenthesis is closed.
+/// Returns `None` once the input is exhausted
+fn read_entry(lines: &mut impl Iterator<Item = io::Result<String>>) -> io::Result<Option<String>> {
+    let mut entry = String::new();
+
+    loop {
+        print!("{}", if entry.is_empty() { "> " } else { "... " });
+        io::stdout().flush()?;
+
+        match lines.next() {
+            Some(line) => {
+                entry.push_str(&line?);
+                entry.push('\n');
+            }
+            None if entry.is_empty() => return Ok(None),
+            None => return Ok(Some(entry)),
+        }
+
+        if unclosed_parentheses(&entry) <= 0 {
+            return Ok(Some(entry));
+        }
+    }
+}
+
+/// The number of parentheses opened but not yet closed, ignoring any inside strings and comments.
+/// A string that is still open counts as one more, so that it can continue on the next line
+fn unclosed_parentheses(source: &str) -> isize {
+    let mut depth = 0;
+
+    for parenthesis in parser::parentheses(source) {
+        match parenthesis {
+            Parenthesis::Open(_) => depth += 1,
+            Parenthesis::Close(_) => depth -= 1,
+            Parenthesis::Unclosed(_) => return depth.max(0) + 1,
+        }
+    }
+
+    depth
 }
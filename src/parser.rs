@@ -1,3 +1,257 @@
+use std::{fmt, iter};
+
+use pest::{
+    error::{ErrorVariant, InputLocation},
+    iterators::Pairs,
+    Parser, Position,
+};
+
+use crate::interpreter::Invocation;
+
 #[derive(Parser)]
 #[grammar = "grammar.pest"]
 pub struct FuncParser;
+
+/// The deepest invocations can be nested in the source. Parsing, checking and evaluating each recurse
+/// once per level on the native stack, so this keeps them from overflowing it on any thread
+pub const MAX_NESTING: usize = 100;
+
+/// A parenthesis found by [`parentheses`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Parenthesis {
+    /// An opening parenthesis at this byte offset
+    Open(usize),
+    /// A closing parenthesis at this byte offset
+    Close(usize),
+    /// A string or block comment starting at this byte offset that is never closed, which ends the source
+    Unclosed(usize),
+}
+
+/// The location of a parsed node in its source
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    /// Byte offset of the first character
+    pub start: usize,
+    /// Byte offset one past the last character
+    pub end: usize,
+    /// 1-based line of the first character
+    pub line: usize,
+    /// 1-based column of the first character
+    pub column: usize,
+}
+
+impl From<pest::Span<'_>> for Span {
+    fn from(value: pest::Span<'_>) -> Self {
+        let (line, column) = value.start_pos().line_col();
+        Self {
+            start: value.start(),
+            end: value.end(),
+            line,
+            column,
+        }
+    }
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+/// Decode the escape sequences of a string literal's `text`, which have already been validated
+/// by the grammar and [`check_escapes`]
+pub fn unescape(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut chars = text.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('n') => result.push('\n'),
+            Some('t') => result.push('\t'),
+            Some('r') => result.push('\r'),
+            Some('0') => result.push('\0'),
+            Some('u') => {
+                let hex = chars
+                    .by_ref()
+                    .skip(1)
+                    .take_while(|&c| c != '}')
+                    .collect::<String>();
+                let code = u32::from_str_radix(&hex, 16).ok();
+                result.extend(code.and_then(char::from_u32));
+            }
+            // `\"` and `\\`
+            Some(c) => result.push(c),
+            None => {}
+        }
+    }
+
+    result
+}
+
+/// A syntax error reported by pest
+pub type ParseError = Box<pest::error::Error<Rule>>;
+
+/// The parentheses of `source` in order, skipping any inside strings and comments
+pub fn parentheses(source: &str) -> impl Iterator<Item = Parenthesis> + '_ {
+    let mut offset = 0;
+
+    iter::from_fn(move || loop {
+        let start = offset;
+        let c = source[start..].chars().next()?;
+        offset += c.len_utf8();
+        let rest = &source[offset..];
+
+        let skipped = match c {
+            '(' => return Some(Parenthesis::Open(start)),
+            ')' => return Some(Parenthesis::Close(start)),
+            '"' => string_length(rest),
+            'r' if rest.trim_start_matches('#').starts_with('"') => {
+                let hashes = rest.len() - rest.trim_start_matches('#').len();
+                let terminator = format!("\"{}", "#".repeat(hashes));
+                rest[hashes + 1..]
+                    .find(&terminator)
+                    .map(|end| hashes + 1 + end + terminator.len())
+            }
+            '#' => Some(rest.find('\n').unwrap_or(rest.len())),
+            '/' if rest.starts_with('/') => Some(rest.find('\n').unwrap_or(rest.len())),
+            '/' if rest.starts_with('*') => rest[1..].find("*/").map(|end| 1 + end + 2),
+            _ => Some(0),
+        };
+
+        match skipped {
+            Some(skipped) => offset += skipped,
+            None => {
+                offset = source.len();
+                return Some(Parenthesis::Unclosed(start));
+            }
+        }
+    })
+}
+
+/// The length of the rest of a string literal after its opening quote, including the closing one
+fn string_length(rest: &str) -> Option<usize> {
+    let mut chars = rest.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return Some(i + 1),
+            '\\' => {
+                chars.next();
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Fail at the first unicode escape in `pairs` that is not a valid character,
+/// such as one past U+10FFFF or a surrogate, which the grammar can not tell apart
+fn check_escapes(pairs: Pairs<Rule>) -> Result<(), ParseError> {
+    for text in pairs.flatten().filter(|pair| pair.as_rule() == Rule::text) {
+        let span = text.as_span();
+        let mut chars = text.as_str().char_indices();
+
+        while let Some((start, c)) = chars.next() {
+            if c != '\\' || chars.next().map(|(_, c)| c) != Some('u') {
+                continue;
+            }
+
+            let hex = chars
+                .by_ref()
+                .skip(1)
+                .take_while(|&(_, c)| c != '}')
+                .map(|(_, c)| c)
+                .collect::<String>();
+            // `\u{`, the digits and `}`
+            let end = start + 3 + hex.len() + 1;
+            if u32::from_str_radix(&hex, 16).ok().and_then(char::from_u32).is_none() {
+                let escape = span.get(start..end).unwrap();
+                return Err(Box::new(pest::error::Error::new_from_span(
+                    ErrorVariant::CustomError {
+                        message: format!("`{}` is not a valid unicode character", escape.as_str()),
+                    },
+                    escape,
+                )));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Fail if invocations in `source` are nested deeper than [`MAX_NESTING`], before pest recurses into them
+fn check_nesting(source: &str) -> Result<(), ParseError> {
+    let mut depth = 0usize;
+
+    for parenthesis in parentheses(source) {
+        match parenthesis {
+            Parenthesis::Open(offset) => {
+                depth += 1;
+                if depth > MAX_NESTING {
+                    return Err(Box::new(pest::error::Error::new_from_pos(
+                        ErrorVariant::CustomError {
+                            message: format!("invocations are nested more than {MAX_NESTING} deep"),
+                        },
+                        Position::new(source, offset).unwrap(),
+                    )));
+                }
+            }
+            Parenthesis::Close(_) => depth = depth.saturating_sub(1),
+            Parenthesis::Unclosed(_) => break,
+        }
+    }
+
+    Ok(())
+}
+
+/// Parse a whole program into its top level [`Invocation`]s
+pub fn parse_program(source: &str) -> Result<Vec<Invocation>, ParseError> {
+    check_nesting(source)?;
+    let mut pairs = FuncParser::parse(Rule::program, source).map_err(Box::new)?;
+    check_escapes(pairs.clone())?;
+
+    Ok(pairs
+        .next()
+        .unwrap()
+        .into_inner()
+        .filter_map(|pair| match pair.as_rule() {
+            Rule::invocation => Some(Invocation::from(pair)),
+            Rule::EOI => None,
+            _ => unreachable!(),
+        })
+        .collect())
+}
+
+/// Whether `source` holds nothing but whitespace and comments
+pub fn is_blank(source: &str) -> bool {
+    FuncParser::parse(Rule::blank, source).is_ok()
+}
+
+/// Parse a single [`Invocation`], optionally followed by a `;`
+pub fn parse_invocation(source: &str) -> Result<Invocation, ParseError> {
+    check_nesting(source)?;
+    let mut pairs = FuncParser::parse(Rule::entry, source).map_err(Box::new)?;
+    check_escapes(pairs.clone())?;
+    let entry = pairs.next().unwrap();
+
+    let end = entry.as_span().end();
+    if let Err(e) = FuncParser::parse(Rule::blank, &source[end..]) {
+        let offset = match e.location {
+            InputLocation::Pos(pos) => pos,
+            InputLocation::Span((start, _)) => start,
+        };
+
+        return Err(Box::new(pest::error::Error::new_from_pos(
+            ErrorVariant::CustomError {
+                message: "expected a single invocation".into(),
+            },
+            Position::new(source, end + offset).unwrap(),
+        )));
+    }
+
+    Ok(Invocation::from(entry.into_inner().next().unwrap()))
+}
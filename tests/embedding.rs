@@ -0,0 +1,29 @@
+//! A host can register its own functions and call the program's, as the README shows
+
+use func_sharp::{
+    interpreter::{consts::*, Data, Interpreter},
+    signature,
+};
+
+fn interpreter() -> Interpreter {
+    let mut interpreter = Interpreter::new();
+    interpreter.register(
+        signature!("double".into(), return_number(), false, arg_number()),
+        |args| Ok(Data::Number(2. * args[0].borrow().number()?)),
+    );
+    interpreter
+}
+
+#[test]
+fn host_functions() {
+    let mut interpreter = interpreter();
+    interpreter.eval_str("let(x, double(21));").unwrap();
+    assert_eq!(*interpreter.get("x").unwrap().borrow(), Data::Number(42.));
+    assert_eq!(*interpreter.call("double", vec![Data::Number(4.)]).unwrap().borrow(), Data::Number(8.));
+}
+
+#[test]
+fn host_call_errors_have_no_location() {
+    let error = interpreter().call("double", vec![Data::String("4".into())]).unwrap_err();
+    assert_eq!(error.to_string(), "function not found: double(string)");
+}
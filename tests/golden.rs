@@ -0,0 +1,214 @@
+//! Runs every `.funcs` program under `tests/` and compares what it printed with the `.expected` file next to it.
+//! A program that is expected to fail has its rendered error in a `.err` file, and a program that reads input
+//! gets it from a `.in` file. An `.args` file gives the limits and module root a program runs with, written as
+//! the options of `func_sharp run`. Programs that compile to bytecode are also run on the stack machine,
+//! which has to behave the same. Set `BLESS=1` to write the expectations from the current behaviour instead
+
+use std::{
+    env, fs,
+    io::Cursor,
+    panic,
+    path::{Path, PathBuf},
+    thread,
+    time::Duration,
+};
+
+use func_sharp::{
+    interpreter::{Capture, Interpreter, IoPolicy, Limits, Runtime},
+    parser,
+};
+
+/// What running a program did
+struct Outcome {
+    output: String,
+    error: Option<String>,
+}
+
+/// The options a program runs with
+struct Options {
+    limits: Limits,
+    module_root: Option<String>,
+}
+
+#[test]
+fn golden() {
+    let handle = thread::Builder::new()
+        .stack_size(limits().stack_size())
+        .spawn(run_all)
+        .unwrap();
+
+    if let Err(panic) = handle.join() {
+        panic::resume_unwind(panic);
+    }
+}
+
+fn run_all() {
+    let bless = env::var_os("BLESS").is_some();
+
+    let mut programs = Vec::new();
+    find_programs(Path::new("tests"), &mut programs);
+    programs.sort();
+    assert!(!programs.is_empty(), "no programs found under tests/");
+
+    let mut failures = Vec::new();
+    for program in programs.iter() {
+        let path = program.to_string_lossy().replace('\\', "/");
+        let source = fs::read_to_string(program).unwrap();
+        let input = fs::read_to_string(program.with_extension("in")).unwrap_or_default();
+        let options = options(&program.with_extension("args"));
+        let expected_path = program.with_extension("expected");
+        let err_path = program.with_extension("err");
+
+        let outcome = run(&path, &source, &input, &options, false).unwrap();
+        if bless {
+            fs::write(&expected_path, &outcome.output).unwrap();
+            match &outcome.error {
+                Some(error) => fs::write(&err_path, format!("{error}\n")).unwrap(),
+                None if err_path.exists() => fs::remove_file(&err_path).unwrap(),
+                None => {}
+            }
+        }
+
+        let Ok(expected_output) = fs::read_to_string(&expected_path) else {
+            failures.push(format!("{path}: {} is missing, run with BLESS=1 to create it", expected_path.display()));
+            continue;
+        };
+        let expected_error = fs::read_to_string(&err_path).ok();
+
+        let compiled = run(&path, &source, &input, &options, true);
+        for (engine, outcome) in [("interpreter", Some(outcome)), ("vm", compiled)] {
+            let Some(outcome) = outcome else {
+                continue;
+            };
+
+            if outcome.output != expected_output {
+                failures.push(mismatch(&path, engine, "output", &expected_output, &outcome.output));
+            }
+            let (expected_error, error) = (expected_error.as_deref().map(str::trim_end), outcome.error.as_deref());
+            if expected_error != error {
+                failures.push(mismatch(
+                    &path,
+                    engine,
+                    "error",
+                    expected_error.unwrap_or("<none>"),
+                    error.unwrap_or("<none>"),
+                ));
+            }
+        }
+    }
+
+    assert!(
+        failures.is_empty(),
+        "found {} differences running {} programs (run with BLESS=1 to accept them):\n\n{}",
+        failures.len(),
+        programs.len(),
+        failures.join("\n\n")
+    );
+}
+
+/// Collect every `.funcs` file under `directory`
+fn find_programs(directory: &Path, programs: &mut Vec<PathBuf>) {
+    for entry in fs::read_dir(directory).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            find_programs(&path, programs);
+        } else if path.extension().is_some_and(|extension| extension == "funcs") {
+            programs.push(path);
+        }
+    }
+}
+
+/// Parse, type check and run a program the way `func_sharp run` does, capturing what it printed.
+/// With `vm` it is compiled to bytecode first, giving `None` if that is not supported
+fn run(path: &str, source: &str, input: &str, options: &Options, vm: bool) -> Option<Outcome> {
+    let output = Capture::new();
+    let failed = |error: String| {
+        Some(Outcome {
+            output: output.take(),
+            error: Some(error),
+        })
+    };
+
+    let mut interpreter = Interpreter::new();
+    interpreter.set_path(path);
+    interpreter.set_limits(options.limits.clone());
+    interpreter.set_io_policy(io_policy(options, &output, input));
+
+    let program = match parser::parse_program(source) {
+        Ok(program) => program,
+        Err(e) => return failed(e.with_path(path).to_string()),
+    };
+
+    if let Err(errors) = interpreter.check(&program) {
+        let rendered = errors.iter().map(|e| e.render(source, path)).collect::<Vec<_>>();
+        return failed(rendered.join("\n"));
+    }
+
+    let result = if vm {
+        let compiled = interpreter.compile(&program).ok()?;
+        let mut runtime = Runtime::default();
+        runtime.set_limits(options.limits.clone());
+        runtime.set_io_policy(io_policy(options, &output, input));
+        compiled.run_with(runtime)
+    } else {
+        interpreter.evaluate(&program)
+    };
+
+    match result {
+        Ok(_) => Some(Outcome {
+            output: output.take(),
+            error: None,
+        }),
+        Err(e) => failed(e.render(source, path)),
+    }
+}
+
+/// The limits of the command line interpreter, also stopping programs that hang so that a regression fails the test
+fn limits() -> Limits {
+    Limits {
+        timeout: Some(Duration::from_secs(10)),
+        ..Limits::default()
+    }
+}
+
+/// Read the options in the `.args` file at `path`, if there is one.
+/// Deeper recursion is not supported, since every program runs on the same thread
+fn options(path: &Path) -> Options {
+    let mut options = Options {
+        limits: limits(),
+        module_root: None,
+    };
+
+    let args = fs::read_to_string(path).unwrap_or_default();
+    let mut args = args.split_whitespace();
+    while let Some(arg) = args.next() {
+        let value = args.next().unwrap_or_else(|| panic!("{}: {arg} takes a value", path.display()));
+        let number = || value.parse().unwrap_or_else(|_| panic!("{}: {arg} takes a number", path.display()));
+
+        match arg {
+            "--max-steps" => options.limits.max_steps = Some(number()),
+            "--max-size" => options.limits.max_size = Some(number()),
+            "--timeout" => options.limits.timeout = Some(Duration::from_millis(number() as u64)),
+            "--module-root" => options.module_root = Some(value.to_string()),
+            _ => panic!("{}: unknown option `{arg}`", path.display()),
+        }
+    }
+
+    options
+}
+
+/// The side effects a program may have: printing to `output`, reading `input` and importing modules
+fn io_policy(options: &Options, output: &Capture, input: &str) -> IoPolicy {
+    let policy = IoPolicy::default()
+        .with_output(output.clone())
+        .with_input(Cursor::new(input.to_string()));
+
+    match &options.module_root {
+        Some(root) => policy.with_imports_within(root),
+        None => policy,
+    }
+}
+
+fn mismatch(path: &str, engine: &str, what: &str, expected: &str, found: &str) -> String {
+    format!("{path} ({engine}): the {what} differs\n--- expected\n{expected}\n--- found\n{found}")
+}
@@ -0,0 +1,46 @@
+//! Programs only have the side effects their [`IoPolicy`] allows, and the rest raise a permission error
+
+use std::io::Cursor;
+
+use func_sharp::interpreter::{Capture, Interpreter, IoPolicy};
+
+/// Run `source` under the policy `policy` makes from where output can be captured,
+/// giving what it printed and the error it stopped with
+fn run(source: &str, policy: impl FnOnce(Capture) -> IoPolicy) -> (String, Option<String>) {
+    let output = Capture::new();
+    let mut interpreter = Interpreter::new();
+    interpreter.set_io_policy(policy(output.clone()));
+
+    let error = interpreter.eval_str(source).err().map(|e| e.to_string());
+    (output.take(), error)
+}
+
+#[test]
+fn output_denied() {
+    let (_, error) = run(r#"println("hi");"#, |_| IoPolicy::deny_all());
+    assert!(error.unwrap().ends_with("permission denied: the program may not write output"));
+}
+
+#[test]
+fn input_denied() {
+    let (output, error) = run(r#"println("reading"); println(stdin());"#, |output| {
+        IoPolicy::deny_all().with_output(output)
+    });
+    assert_eq!(output, "reading\n");
+    assert!(error.unwrap().ends_with("permission denied: the program may not read input"));
+}
+
+#[test]
+fn input_allowed() {
+    let (output, error) = run("println(trim(stdin()));", |output| {
+        IoPolicy::deny_all().with_output(output).with_input(Cursor::new("typed\n"))
+    });
+    assert_eq!((output.as_str(), error), ("typed\n", None));
+}
+
+#[test]
+fn imports_denied() {
+    let source = r#"import("tests/programs/modules/lib/math.funcs");"#;
+    let (_, error) = run(source, |output| IoPolicy::default().with_output(output).deny_imports());
+    assert!(error.unwrap().ends_with("permission denied: the program may not import files"));
+}